@@ -0,0 +1,371 @@
+//! Derive macros of `wgpu-cpp`.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Data, DeriveInput, Error, Expr, ExprLit,
+    Fields, Ident, Lit, Path, Result, Type,
+};
+
+/// Implements `VertexAttributeLayout` with one attribute per field, in declaration order.
+///
+/// Shader locations count up from 0, `#[location = N]` moves a field and the ones after
+/// it. The vertex format follows from the field type: `f32`, `u32`, `i32`, glam vectors,
+/// arrays of them, and normalized `[u8; N]`, `[i8; N]`, `[u16; N]` and `[i16; N]`.
+///
+/// - `#[vertex(format = Float32x4)]` on a field overrides its format, e.g. to read the
+///   field after it in the same attribute.
+/// - `#[vertex(skip)]` on a field leaves it out.
+/// - `#[vertex(instance)]` on the struct steps the buffer per instance.
+/// - `#[vertex(crate = path)]` on the struct names the module exporting the trait,
+///   `crate::resources` by default.
+///
+/// The struct must be `#[repr(C)]`, offsets come from `std::mem::offset_of!`.
+#[proc_macro_derive(VertexAttributeLayout, attributes(location, vertex))]
+pub fn derive_vertex_attribute_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(
+            Span::call_site(),
+            "only structs have a vertex layout",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new(
+            data.fields.span(),
+            "vertex fields must be named",
+        ));
+    };
+
+    let mut step_mode = format_ident!("Vertex");
+    let mut resources: Path = parse_quote!(crate::resources);
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("vertex"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("instance") {
+                step_mode = format_ident!("Instance");
+                Ok(())
+            } else if meta.path.is_ident("crate") {
+                resources = meta.value()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `instance` or `crate = ...`"))
+            }
+        })?;
+    }
+
+    let mut attributes = vec![];
+    let mut location = 0_u32;
+    for field in &fields.named {
+        let field_name = field
+            .ident
+            .as_ref()
+            .expect("named fields have an identifier");
+        let mut skip = false;
+        let mut format = None;
+        for attr in &field.attrs {
+            if attr.path().is_ident("location") {
+                let value = &attr.meta.require_name_value()?.value;
+                let Expr::Lit(ExprLit {
+                    lit: Lit::Int(value),
+                    ..
+                }) = value
+                else {
+                    return Err(Error::new(value.span(), "expected `#[location = N]`"));
+                };
+                location = value.base10_parse()?;
+            } else if attr.path().is_ident("vertex") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("skip") {
+                        skip = true;
+                        Ok(())
+                    } else if meta.path.is_ident("format") {
+                        format = Some(meta.value()?.parse::<Ident>()?);
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `skip` or `format = ...`"))
+                    }
+                })?;
+            }
+        }
+        if skip {
+            continue;
+        }
+        let format = match format {
+            Some(format) => format,
+            None => vertex_format(&field.ty)?,
+        };
+        attributes.push(quote! {
+            ::wgpu::VertexAttribute {
+                format: ::wgpu::VertexFormat::#format,
+                offset: ::std::mem::offset_of!(#name, #field_name) as ::wgpu::BufferAddress,
+                shader_location: #location,
+            }
+        });
+        location += 1;
+    }
+
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #resources::VertexAttributeLayout for #name #type_generics
+        #where_clause
+        {
+            fn layout() -> ::wgpu::VertexBufferLayout<'static> {
+                const ATTRIBUTES: &[::wgpu::VertexAttribute] = &[#(#attributes),*];
+                ::wgpu::VertexBufferLayout {
+                    array_stride: ::std::mem::size_of::<Self>() as ::wgpu::BufferAddress,
+                    step_mode: ::wgpu::VertexStepMode::#step_mode,
+                    attributes: ATTRIBUTES,
+                }
+            }
+        }
+    })
+}
+
+/// Variant of `wgpu::VertexFormat` matching `ty`.
+fn vertex_format(ty: &Type) -> Result<Ident> {
+    let unsupported = || {
+        Error::new(
+            ty.span(),
+            "no vertex format for this type, set one with `#[vertex(format = ...)]`",
+        )
+    };
+    let format = match ty {
+        Type::Path(path) => {
+            let ident = &path.path.segments.last().ok_or_else(unsupported)?.ident;
+            match ident.to_string().as_str() {
+                "f32" => "Float32",
+                "u32" => "Uint32",
+                "i32" => "Sint32",
+                "Vec2" => "Float32x2",
+                "Vec3" => "Float32x3",
+                "Vec4" | "Quat" => "Float32x4",
+                "UVec2" => "Uint32x2",
+                "UVec3" => "Uint32x3",
+                "UVec4" => "Uint32x4",
+                "IVec2" => "Sint32x2",
+                "IVec3" => "Sint32x3",
+                "IVec4" => "Sint32x4",
+                _ => return Err(unsupported()),
+            }
+            .to_owned()
+        }
+        Type::Array(array) => {
+            let Type::Path(element) = &*array.elem else {
+                return Err(unsupported());
+            };
+            let element = element.path.get_ident().ok_or_else(unsupported)?;
+            let Expr::Lit(ExprLit {
+                lit: Lit::Int(len), ..
+            }) = &array.len
+            else {
+                return Err(unsupported());
+            };
+            let len: u32 = len.base10_parse()?;
+            // Narrow integers are only read normalized and in pairs or quads.
+            match (element.to_string().as_str(), len) {
+                ("f32", 1) => "Float32".to_owned(),
+                ("u32", 1) => "Uint32".to_owned(),
+                ("i32", 1) => "Sint32".to_owned(),
+                ("f32", 2..=4) => format!("Float32x{len}"),
+                ("u32", 2..=4) => format!("Uint32x{len}"),
+                ("i32", 2..=4) => format!("Sint32x{len}"),
+                ("u8", 2 | 4) => format!("Unorm8x{len}"),
+                ("i8", 2 | 4) => format!("Snorm8x{len}"),
+                ("u16", 2 | 4) => format!("Unorm16x{len}"),
+                ("i16", 2 | 4) => format!("Snorm16x{len}"),
+                _ => return Err(unsupported()),
+            }
+        }
+        _ => return Err(unsupported()),
+    };
+    Ok(Ident::new(&format, ty.span()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_to_string(input: &DeriveInput) -> String {
+        expand(input).unwrap().to_string()
+    }
+
+    fn expand_error(input: &DeriveInput) -> String {
+        expand(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn fields_count_up_from_their_location() {
+        let input = parse_quote! {
+            #[repr(C)]
+            struct Vertex {
+                position: Vec3,
+                #[location = 4]
+                color: [u8; 4],
+                #[vertex(skip)]
+                padding: u32,
+                uv: [f32; 2],
+            }
+        };
+        let expected = quote! {
+            impl crate::resources::VertexAttributeLayout for Vertex {
+                fn layout() -> ::wgpu::VertexBufferLayout<'static> {
+                    const ATTRIBUTES: &[::wgpu::VertexAttribute] = &[
+                        ::wgpu::VertexAttribute {
+                            format: ::wgpu::VertexFormat::Float32x3,
+                            offset: ::std::mem::offset_of!(Vertex, position) as ::wgpu::BufferAddress,
+                            shader_location: 0u32,
+                        },
+                        ::wgpu::VertexAttribute {
+                            format: ::wgpu::VertexFormat::Unorm8x4,
+                            offset: ::std::mem::offset_of!(Vertex, color) as ::wgpu::BufferAddress,
+                            shader_location: 4u32,
+                        },
+                        ::wgpu::VertexAttribute {
+                            format: ::wgpu::VertexFormat::Float32x2,
+                            offset: ::std::mem::offset_of!(Vertex, uv) as ::wgpu::BufferAddress,
+                            shader_location: 5u32,
+                        }
+                    ];
+                    ::wgpu::VertexBufferLayout {
+                        array_stride: ::std::mem::size_of::<Self>() as ::wgpu::BufferAddress,
+                        step_mode: ::wgpu::VertexStepMode::Vertex,
+                        attributes: ATTRIBUTES,
+                    }
+                }
+            }
+        };
+        assert_eq!(expand_to_string(&input), expected.to_string());
+    }
+
+    #[test]
+    fn format_overrides_the_field_type() {
+        let input = parse_quote! {
+            struct Transform {
+                #[vertex(format = Float32x4)]
+                row: f32,
+            }
+        };
+        let expanded = expand_to_string(&input);
+        assert!(expanded.contains("VertexFormat :: Float32x4"), "{expanded}");
+        assert!(
+            !expanded.contains("VertexFormat :: Float32 ,"),
+            "{expanded}"
+        );
+    }
+
+    #[test]
+    fn instance_steps_per_instance() {
+        let input = parse_quote! {
+            #[vertex(instance)]
+            struct Instance {
+                offset: Vec3,
+            }
+        };
+        let expanded = expand_to_string(&input);
+        assert!(
+            expanded.contains("VertexStepMode :: Instance"),
+            "{expanded}"
+        );
+    }
+
+    #[test]
+    fn crate_names_the_trait_module() {
+        let input = parse_quote! {
+            #[vertex(crate = ::wgpu_cpp::resources, instance)]
+            struct Instance<T> where T: Copy {
+                offset: [f32; 3],
+            }
+        };
+        let expanded = expand_to_string(&input);
+        assert!(
+            expanded.starts_with(
+                &quote!(impl<T> ::wgpu_cpp::resources::VertexAttributeLayout for Instance<T> where T: Copy)
+                    .to_string()
+            ),
+            "{expanded}"
+        );
+        assert!(
+            expanded.contains("VertexStepMode :: Instance"),
+            "{expanded}"
+        );
+    }
+
+    #[test]
+    fn invalid_input_is_reported() {
+        let cases: [(DeriveInput, &str); 7] = [
+            (
+                parse_quote!(
+                    enum Vertex {
+                        A,
+                    }
+                ),
+                "only structs have a vertex layout",
+            ),
+            (
+                parse_quote!(
+                    struct Vertex(f32);
+                ),
+                "vertex fields must be named",
+            ),
+            (
+                parse_quote!(
+                    #[vertex(index)]
+                    struct Vertex {
+                        a: f32,
+                    }
+                ),
+                "expected `instance` or `crate = ...`",
+            ),
+            (
+                parse_quote!(
+                    struct Vertex {
+                        #[vertex(hidden)]
+                        a: f32,
+                    }
+                ),
+                "expected `skip` or `format = ...`",
+            ),
+            (
+                parse_quote!(
+                    struct Vertex {
+                        #[location = "1"]
+                        a: f32,
+                    }
+                ),
+                "expected `#[location = N]`",
+            ),
+            (
+                parse_quote!(
+                    struct Vertex {
+                        #[location(1)]
+                        a: f32,
+                    }
+                ),
+                "expected `=`",
+            ),
+            (
+                parse_quote!(
+                    struct Vertex {
+                        a: [u8; 3],
+                    }
+                ),
+                "no vertex format for this type, set one with `#[vertex(format = ...)]`",
+            ),
+        ];
+        for (input, message) in cases {
+            assert_eq!(expand_error(&input), message);
+        }
+    }
+}
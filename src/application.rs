@@ -1,20 +1,31 @@
 #![allow(clippy::module_name_repetitions)]
 pub mod bind_group;
 pub mod buffer;
+pub mod environment;
+pub mod light;
+pub mod material;
+pub mod pipeline_cache;
+pub mod reflection;
 pub mod render_pipeline;
+pub mod shadow;
+pub mod skybox;
 pub mod texture;
+pub mod tonemap;
 pub mod wgpu_context;
 
 use std::{
     f32::consts::PI,
-    sync::Arc,
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
     time::{self, Duration, Instant},
 };
 
 use egui_wgpu::ScreenDescriptor;
 use glam::{Mat3, Mat4, Quat, Vec3, Vec4};
+use image::RgbaImage;
+use tracing::{error, info};
 
-use tracing::info;
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
     event::{ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
@@ -25,36 +36,121 @@ use winit::{
 
 use crate::{
     compute,
+    error::{Error, Result},
     gui::{EguiRenderer, GuiState},
-    resources::{load_geometry, VertexAttribute},
+    resources::{
+        load_mesh, read_texture, save_texture, ColorSpace, CubemapSource, LoadOptions,
+        MaterialDescriptor, Mesh, PackedVertex, Submesh, VertexEncoding,
+    },
 };
 
 use self::{
-    bind_group::BindGroup,
-    buffer::{DataBuffer, IndexBuffer, VertexBuffer},
-    texture::Texture,
+    bind_group::{BindGroup, BindResource},
+    buffer::{DataBuffer, IndexBuffer, StorageBuffer, VertexBuffer},
+    environment::{Environment, DEFAULT_AMBIENT},
+    light::{Light, LightData},
+    material::{Material, ShadingModel},
+    pipeline_cache::{PipelineCache, PipelineCacheStats},
+    reflection::ShaderReflection,
+    render_pipeline::RenderPipeline,
+    shadow::{assign_shadow_layers, bounding_sphere, ShadowMaps, ShadowSettings},
+    skybox::Skybox,
+    texture::{FallbackTextures, Texture},
+    tonemap::{TonemapPass, Tonemapping},
     wgpu_context::WgpuContext,
 };
+
+/// Bindings of `shader.wgsl`: the scene in group 0, materials in 1, shadows in 2 and
+/// environment lighting in 3.
+pub static SHADER_BINDINGS: LazyLock<ShaderReflection> = LazyLock::new(|| {
+    ShaderReflection::from_wgsl("shader.wgsl", include_str!("shader.wgsl"))
+        .expect("shader.wgsl is valid WGSL")
+});
+/// Assets, lights and camera an `ApplicationState` starts with.
+pub struct SceneDescriptor {
+    pub mesh: PathBuf,
+    pub load_options: LoadOptions,
+    pub lights: Vec<Light>,
+    /// Shading parameters, `light_count` is taken from `lights`.
+    pub lighting: LightUniforms,
+    pub camera: Camera,
+    /// Exposure in stops applied before tonemapping.
+    pub exposure: f32,
+    pub tonemapping: Tonemapping,
+    pub shadows: ShadowSettings,
+    pub shading_model: ShadingModel,
+    /// Equirectangular `.hdr` image lighting the scene and drawn behind it. Without
+    /// one the clear color stays visible and the ambient light is `DEFAULT_AMBIENT`.
+    pub environment: Option<PathBuf>,
+    /// Scale of the environment radiance.
+    pub environment_intensity: f32,
+    /// sRGB cubemap drawn behind the scene instead of the environment, which
+    /// still lights it.
+    pub skybox: Option<CubemapSource>,
+}
+
+impl Default for SceneDescriptor {
+    fn default() -> Self {
+        Self {
+            mesh: "resources/fourareen/fourareen.obj".into(),
+            load_options: LoadOptions {
+                cache: true,
+                ..Default::default()
+            },
+            lights: vec![
+                Light::directional(Vec3::new(0.5, -0.9, 0.1), Vec3::new(1.0, 0.9, 0.6)),
+                Light::directional(Vec3::new(0.2, 0.4, 0.3), Vec3::new(0.6, 0.9, 1.0)),
+            ],
+            lighting: LightUniforms {
+                hardness: 16.0,
+                diffuse: 1.0,
+                specular: 0.5,
+                ..Default::default()
+            },
+            camera: Camera {
+                orbit_radius: 2.0,
+                ..Default::default()
+            },
+            exposure: 0.0,
+            tonemapping: Tonemapping::default(),
+            shadows: ShadowSettings::default(),
+            shading_model: ShadingModel::default(),
+            environment: Some("resources/sky.hdr".into()),
+            environment_intensity: 1.0,
+            skybox: None,
+        }
+    }
+}
+
 pub struct ApplicationState {
     wgpu: WgpuContext,
-    depth_texture: Texture,
-    texture: Texture,
-    normal_texture: Texture,
-    vertex_buffer: VertexBuffer<VertexAttribute>,
+    /// The scene is rendered here, then resolved to the frame by `tonemap`.
+    hdr_texture: Texture,
+    tonemap: TonemapPass,
+    shadow_maps: ShadowMaps,
+    environment: Environment,
+    skybox: Skybox,
+    materials: Vec<Material>,
+    /// Used by submeshes without a material.
+    default_material: Material,
+    vertex_buffer: VertexBuffer,
     index_buffer: IndexBuffer,
+    submeshes: Vec<Submesh>,
     uniforms: DataBuffer<Uniforms>,
     bind_group: BindGroup,
-    render_pipeline: render_pipeline::RenderPipeline,
+    render_pipeline: RenderPipeline,
+    pipeline_cache: PipelineCache,
 
     start_time: Instant,
     delta_time: Duration,
     camera: Camera,
     mouse_pos: PhysicalPosition<f64>,
     drag: bool,
-    egui: EguiRenderer,
-    window: Arc<Window>,
+    egui: Option<EguiRenderer>,
+    window: Option<Arc<Window>>,
     gui_state: GuiState,
     light_uniforms: DataBuffer<LightUniforms>,
+    lights: StorageBuffer<LightData>,
 
     input_texture: Texture,
     compute_uniforms: DataBuffer<ComputeUniforms>,
@@ -62,25 +158,126 @@ pub struct ApplicationState {
 }
 
 impl ApplicationState {
-    #[allow(clippy::too_many_lines)]
-    pub fn new(window: &Arc<Window>) -> Self {
-        let size = window.inner_size();
+    pub fn new(window: &Arc<Window>, scene: &SceneDescriptor) -> Result<Self> {
         let wgpu = WgpuContext::new(window);
-        let depth_texture = Texture::depth(&wgpu.device, size.width, size.height);
-        let texture = Texture::new("resources/fourareen/fourareen2K_albedo.jpg", &wgpu);
-        let normal_texture = Texture::new("resources/fourareen/fourareen2K_normals.png", &wgpu);
+        Self::from_context(wgpu, Some(window), scene)
+    }
 
-        let (vertices, indices) = load_geometry("resources/fourareen/fourareen.obj");
-        let vertex_buffer = VertexBuffer::new(vertices, &wgpu.device);
-        let index_buffer = IndexBuffer::new(indices, &wgpu.device);
+    /// Creates a state that renders offscreen, without a window, surface or GUI.
+    ///
+    /// Returns `None` when there is no adapter to render with.
+    pub fn headless(width: u32, height: u32, scene: &SceneDescriptor) -> Result<Option<Self>> {
+        WgpuContext::headless(width, height)
+            .map(|wgpu| Self::from_context(wgpu, None, scene))
+            .transpose()
+    }
+
+    /// Assets that fail to load are reported in the GUI and replaced by fallbacks,
+    /// errors without a fallback are returned.
+    #[allow(clippy::too_many_lines)]
+    fn from_context(
+        wgpu: WgpuContext,
+        window: Option<&Arc<Window>>,
+        scene: &SceneDescriptor,
+    ) -> Result<Self> {
+        let size = PhysicalSize::new(wgpu.config.width, wgpu.config.height);
+        let depth_format = wgpu.depth_texture.texture.format();
+        let hdr_texture = Texture::hdr_target(&wgpu.device, size.width, size.height);
+        let mut pipeline_cache = PipelineCache::default();
+        let tonemap = TonemapPass::new(
+            &wgpu.device,
+            &mut pipeline_cache,
+            &hdr_texture,
+            wgpu.config.format,
+            scene.exposure,
+            scene.tonemapping,
+        )?;
+        // Loading errors are shown in the GUI instead of aborting.
+        let mut errors = vec![];
+        let mut report = |error: Error| {
+            error!("{error}");
+            errors.push(error.to_string());
+        };
+        let mesh = load_mesh(&scene.mesh, scene.load_options).unwrap_or_else(|e| {
+            report(e);
+            Mesh::default()
+        });
+        let vertex_encoding = scene.load_options.vertex_encoding;
+        let input_texture = Texture::new(
+            "resources/butterfly.jpg",
+            ColorSpace::Linear,
+            &wgpu,
+            &mut pipeline_cache,
+        )
+        .or_else(|e| {
+            report(e);
+            Texture::solid([255, 255, 255, 255], ColorSpace::Linear, &wgpu)
+        })?;
+        let loaded_environment = scene.environment.as_ref().and_then(|path| {
+            Environment::load(path, &wgpu.device, &wgpu.queue, &mut pipeline_cache)
+                .map_err(&mut report)
+                .ok()
+        });
+        let skybox_cubemap = scene.skybox.as_ref().and_then(|source| {
+            match source {
+                CubemapSource::Faces(paths) => {
+                    Texture::cube_from_faces(paths, ColorSpace::Srgb, &wgpu, &mut pipeline_cache)
+                }
+                CubemapSource::Cross(path) => {
+                    Texture::cube_from_cross(path, ColorSpace::Srgb, &wgpu, &mut pipeline_cache)
+                }
+            }
+            .map_err(&mut report)
+            .ok()
+        });
+        let show_skybox = loaded_environment.is_some() || skybox_cubemap.is_some();
+        let environment = match loaded_environment {
+            Some(environment) => environment,
+            None => Environment::uniform(
+                DEFAULT_AMBIENT,
+                &wgpu.device,
+                &wgpu.queue,
+                &mut pipeline_cache,
+            )?,
+        };
+        let mut skybox = Skybox::new(
+            &wgpu.device,
+            &mut pipeline_cache,
+            skybox_cubemap.map_or_else(|| environment.cubemap.clone(), Arc::new),
+            depth_format,
+        )?;
+        // Missing textures are already logged as warnings by `Material::new`.
+        let fallbacks = FallbackTextures::new(&wgpu)?;
+        let materials = mesh
+            .materials
+            .iter()
+            .map(|material| {
+                Material::new(material, &fallbacks, &wgpu, &mut pipeline_cache, |e| {
+                    errors.push(e.to_string());
+                })
+            })
+            .collect::<Result<_>>()?;
+        let default_material = Material::new(
+            &MaterialDescriptor::default(),
+            &fallbacks,
+            &wgpu,
+            &mut pipeline_cache,
+            |_| {},
+        )?;
+        let shadow_maps = ShadowMaps::new(
+            &wgpu.device,
+            &mut pipeline_cache,
+            scene.shadows,
+            bounding_sphere(&mesh.vertices),
+            vertex_encoding.layout(),
+        )?;
+        let vertex_buffer = Self::create_vertex_buffer(&mesh, vertex_encoding, &wgpu.device);
+        let index_buffer = IndexBuffer::new(&mesh.indices, &wgpu.device);
 
         let start_time = time::Instant::now();
         let aspect = size.width as f32 / size.height as f32;
 
-        let camera = Camera {
-            orbit_radius: 2.0,
-            ..Default::default()
-        };
+        let camera = scene.camera;
 
         let uniforms = Uniforms {
             model: Mat4::IDENTITY,
@@ -94,90 +291,123 @@ impl ApplicationState {
             ..Default::default()
         };
         let uniform_buffer = DataBuffer::uniform(uniforms, &wgpu.device);
+        skybox.set_camera(uniforms.view, uniforms.projection);
+        skybox.uniforms.data.intensity = scene.environment_intensity;
+        skybox.uniforms.update(&wgpu.queue);
 
         let light_uniforms = DataBuffer::uniform(
             LightUniforms {
-                directions: [[0.5, -0.9, 0.1, 0.0].into(), [0.2, 0.4, 0.3, 0.0].into()],
-                colors: [[1.0, 0.9, 0.6, 1.0].into(), [0.6, 0.9, 1.0, 1.0].into()],
-                hardness: 16.0,
-                diffuse: 1.0,
-                specular: 0.5,
-                ..Default::default()
+                light_count: scene.lights.len() as u32,
+                shading_model: scene.shading_model as u32,
+                environment_intensity: scene.environment_intensity,
+                ..scene.lighting
             },
             &wgpu.device,
         );
+        let mut light_data: Vec<_> = scene.lights.iter().map(Light::data).collect();
+        assign_shadow_layers(&mut light_data);
+        let lights = StorageBuffer::new(light_data, &wgpu.device);
 
-        let bind_group = BindGroup::new(
+        let bind_group = Self::create_bind_group(
             &wgpu.device,
-            &[&uniform_buffer.buffer, &light_uniforms.buffer],
-            &[&texture, &normal_texture],
-        );
-        let render_pipeline = render_pipeline::RenderPipeline::new::<VertexAttribute>(
-            &wgpu.device,
-            &bind_group.bind_group_layout,
-            depth_texture.texture.format(),
-            wgpu.config.format,
-            wgpu::include_wgsl!("shader.wgsl"),
-        );
-
-        let egui = EguiRenderer::new(
-            &wgpu.device,       // wgpu Device
-            wgpu.config.format, // TextureFormat
-            None,               // this can be None
-            1,                  // samples
-            window,             // winit Window
-        );
+            &mut pipeline_cache,
+            &uniform_buffer,
+            &light_uniforms,
+            &lights,
+        )?;
+        let shader = pipeline_cache.shader(&wgpu.device, wgpu::include_wgsl!("shader.wgsl"));
+        let builder = RenderPipeline::builder("Render Pipeline", &shader)
+            .vertex_entry_point(vertex_encoding.vertex_entry_point())
+            .vertex_buffer_layout(vertex_encoding.layout())
+            .bind_group_layouts(&[
+                &bind_group.bind_group_layout,
+                &default_material.bind_group.bind_group_layout,
+                &shadow_maps.bind_group.bind_group_layout,
+                &environment.bind_group.bind_group_layout,
+            ])
+            .depth(depth_format, true, wgpu::CompareFunction::LessEqual)
+            .color_target(
+                Texture::HDR_FORMAT,
+                Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+            );
+        let render_pipeline = pipeline_cache.render_pipeline(&wgpu.device, builder);
+
+        let egui = window.map(|window| {
+            EguiRenderer::new(
+                &wgpu.device,       // wgpu Device
+                wgpu.config.format, // TextureFormat
+                None,               // this can be None
+                1,                  // samples
+                window,             // winit Window
+            )
+        });
         let compute_uniforms = DataBuffer::uniform(
-            ComputeUniforms {
-                kernel: Mat3::from_cols_array_2d(&[
-                    [-1.0, -2.0, -1.0],
-                    [0.0, 0.0, 0.0],
-                    [1.0, 2.0, 1.0],
-                ]),
-                test: 0.5,
-                ..Default::default()
-            },
+            ComputeUniforms::new(
+                Mat3::from_cols_array_2d(&[[-1.0, -2.0, -1.0], [0.0, 0.0, 0.0], [1.0, 2.0, 1.0]]),
+                0.5,
+            ),
             &wgpu.device,
         );
         let gui_state = GuiState {
             clear_color: [0.05, 0.05, 0.05],
-            light_color1: light_uniforms.data.colors[0].truncate().to_array(),
-            light_color2: light_uniforms.data.colors[1].truncate().to_array(),
-            light_direction1: light_uniforms.data.directions[0],
-            light_direction2: light_uniforms.data.directions[1],
+            lights: scene.lights.clone(),
             hardness: light_uniforms.data.hardness,
             diffuse: light_uniforms.data.diffuse,
             specular: light_uniforms.data.specular,
             normal_strength: uniform_buffer.data.normal_map_strength,
             mip_level: uniform_buffer.data.mip_level,
-            kernel: compute_uniforms.data.kernel,
+            kernel: compute_uniforms.data.kernel(),
             compute_test: compute_uniforms.data.test,
+            exposure: scene.exposure,
+            tonemapping: scene.tonemapping,
+            shadows: scene.shadows,
+            shading_model: scene.shading_model,
+            skybox: show_skybox,
+            environment_intensity: scene.environment_intensity,
+            errors,
+            pipeline_cache: PipelineCacheStats::default(),
         };
-        let input_texture = Texture::new("resources/butterfly.jpg", &wgpu);
-
-        Self {
+        Ok(Self {
             wgpu,
-            depth_texture,
-            texture,
-            normal_texture,
+            hdr_texture,
+            tonemap,
+            shadow_maps,
+            environment,
+            skybox,
+            materials,
+            default_material,
             vertex_buffer,
             index_buffer,
+            submeshes: mesh.submeshes,
             uniforms: uniform_buffer,
             bind_group,
             render_pipeline,
+            pipeline_cache,
             start_time,
             delta_time: Duration::from_secs_f64(1.0 / 144.0),
             mouse_pos: PhysicalPosition::default(),
             camera,
             drag: false,
             egui,
-            window: window.clone(),
+            window: window.cloned(),
             gui_state,
             light_uniforms,
+            lights,
             input_texture,
             compute_uniforms,
             should_compute: true,
-        }
+        })
     }
 
     pub fn update(&mut self) {
@@ -189,8 +419,24 @@ impl ApplicationState {
         self.uniforms.data.camera_world_position = self.camera.get_translation();
 
         self.uniforms.update(&self.wgpu.queue);
+        self.skybox
+            .set_camera(self.uniforms.data.view, self.uniforms.data.projection);
+        self.skybox.uniforms.update(&self.wgpu.queue);
         self.light_uniforms.update(&self.wgpu.queue);
+        if self.lights.update(&self.wgpu.device, &self.wgpu.queue) {
+            match Self::create_bind_group(
+                &self.wgpu.device,
+                &mut self.pipeline_cache,
+                &self.uniforms,
+                &self.light_uniforms,
+                &self.lights,
+            ) {
+                Ok(bind_group) => self.bind_group = bind_group,
+                Err(e) => self.report_error(&e),
+            }
+        }
         self.compute_uniforms.update(&self.wgpu.queue);
+        self.tonemap.uniforms.update(&self.wgpu.queue);
 
         self.compute();
         self.render();
@@ -198,22 +444,36 @@ impl ApplicationState {
         let end_frame_time = time::Instant::now();
         self.delta_time = end_frame_time - begin_frame_time;
     }
+    #[allow(clippy::too_many_lines)]
     pub fn render(&mut self) {
-        let output = self.wgpu.get_current_texture();
-
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let frame = self.wgpu.current_frame();
+        let view = &frame.view;
 
         let mut encoder = self
             .wgpu
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        if let Err(e) = self.shadow_maps.prepare(
+            &self.wgpu.device,
+            &self.wgpu.queue,
+            &mut self.pipeline_cache,
+            &self.lights.data,
+            self.uniforms.data.view,
+            self.uniforms.data.projection,
+        ) {
+            self.report_error(&e);
+        }
+        self.shadow_maps.draw(
+            &mut encoder,
+            &self.vertex_buffer,
+            &self.index_buffer,
+            &self.submeshes,
+        );
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: &self.hdr_texture.view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -226,7 +486,7 @@ impl ApplicationState {
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
+                    view: &self.wgpu.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Store,
@@ -238,78 +498,184 @@ impl ApplicationState {
             });
 
             render_pass.set_pipeline(&self.render_pipeline.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
-            render_pass.set_index_buffer(
-                self.index_buffer.buffer.slice(..),
-                wgpu::IndexFormat::Uint32,
-            );
             render_pass.set_bind_group(0, &self.bind_group.bind_group, &[]);
-            render_pass.draw_indexed(0..self.index_buffer.indices.len() as u32, 0, 0..1);
+            render_pass.set_bind_group(2, &self.shadow_maps.bind_group.bind_group, &[]);
+            render_pass.set_bind_group(3, &self.environment.bind_group.bind_group, &[]);
+            // Empty buffers cannot be bound, e.g. when the mesh failed to load.
+            if self.vertex_buffer.vertex_count > 0 && self.index_buffer.index_count > 0 {
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
+                render_pass
+                    .set_index_buffer(self.index_buffer.buffer.slice(..), self.index_buffer.format);
+                for submesh in &self.submeshes {
+                    let material = submesh
+                        .material_id
+                        .and_then(|id| self.materials.get(id))
+                        .unwrap_or(&self.default_material);
+                    render_pass.set_bind_group(1, &material.bind_group.bind_group, &[]);
+                    render_pass.draw_indexed(submesh.indices.clone(), submesh.base_vertex, 0..1);
+                }
+            }
+            // Only fills the pixels no geometry was drawn to.
+            if self.gui_state.skybox {
+                self.skybox.draw(&mut render_pass);
+            }
         }
-        let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [self.wgpu.config.width, self.wgpu.config.height],
-            pixels_per_point: self.window.scale_factor() as f32,
-        };
+        self.tonemap.draw(&mut encoder, view);
+        self.gui_state.pipeline_cache = self.pipeline_cache.stats;
         let old_compute_test = self.gui_state.compute_test;
         let old_kernel = self.gui_state.kernel;
-        self.egui.draw(
-            &self.wgpu.device,
-            &self.wgpu.queue,
-            &mut encoder,
-            &self.window,
-            &view,
-            &screen_descriptor,
-            |ui| self.gui_state.gui(ui, self.delta_time),
-        );
+        if let (Some(egui), Some(window)) = (&mut self.egui, &self.window) {
+            let screen_descriptor = ScreenDescriptor {
+                size_in_pixels: [self.wgpu.config.width, self.wgpu.config.height],
+                pixels_per_point: window.scale_factor() as f32,
+            };
+            egui.draw(
+                &self.wgpu.device,
+                &self.wgpu.queue,
+                &mut encoder,
+                window,
+                view,
+                &screen_descriptor,
+                |ui| self.gui_state.gui(ui, self.delta_time),
+            );
+        }
         self.light_uniforms.data = LightUniforms {
-            directions: [
-                self.gui_state.light_direction1,
-                self.gui_state.light_direction2,
-            ],
-            colors: [
-                Vec3::from(self.gui_state.light_color1).extend(1.0),
-                Vec3::from(self.gui_state.light_color2).extend(1.0),
-            ],
+            light_count: self.gui_state.lights.len() as u32,
             hardness: self.gui_state.hardness,
             diffuse: self.gui_state.diffuse,
             specular: self.gui_state.specular,
-            _padding: Default::default(),
+            shading_model: self.gui_state.shading_model as u32,
+            environment_intensity: self.gui_state.environment_intensity,
+            ..Default::default()
         };
+        self.skybox.uniforms.data.intensity = self.gui_state.environment_intensity;
+        self.lights.data = self.gui_state.lights.iter().map(Light::data).collect();
+        assign_shadow_layers(&mut self.lights.data);
+        self.shadow_maps.settings = self.gui_state.shadows;
         self.uniforms.data.normal_map_strength = self.gui_state.normal_strength;
         self.uniforms.data.mip_level = self.gui_state.mip_level;
-        self.compute_uniforms.data.kernel = self.gui_state.kernel;
-        self.compute_uniforms.data.test = self.gui_state.compute_test;
+        self.tonemap.uniforms.data.exposure = self.gui_state.exposure.exp2();
+        self.tonemap.uniforms.data.tonemapping = self.gui_state.tonemapping as u32;
+        self.compute_uniforms.data =
+            ComputeUniforms::new(self.gui_state.kernel, self.gui_state.compute_test);
 
         if f32::abs(old_compute_test - self.compute_uniforms.data.test) > f32::EPSILON {
             self.should_compute = true;
         }
-        if old_kernel != self.compute_uniforms.data.kernel {
+        if old_kernel != self.gui_state.kernel {
             self.should_compute = true;
         }
         let command = encoder.finish();
 
         self.wgpu.queue.submit([command]);
 
-        output.present();
+        frame.present();
+    }
+
+    /// Copies the current offscreen frame to an image file.
+    pub fn save_frame(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.read_frame()?.save(&path).map_err(|e| Error::Image {
+            name: path.as_ref().display().to_string(),
+            source: e,
+        })
+    }
+
+    /// Reads the current offscreen frame back to the CPU.
+    pub fn read_frame(&self) -> Result<RgbaImage> {
+        let texture = self.wgpu.offscreen.as_ref().ok_or_else(|| {
+            Error::Readback("only headless states have an offscreen frame".to_owned())
+        })?;
+        read_texture(texture, &self.wgpu.device, &self.wgpu.queue, 0)
     }
 
     pub fn compute(&mut self) {
         if self.should_compute {
-            compute::compute_filter(
+            let result = compute::compute_filter(
                 &self.input_texture,
                 &self.compute_uniforms,
+                &mut self.pipeline_cache,
                 &self.wgpu.device,
                 &self.wgpu.queue,
-            );
+            )
+            .and_then(|output_texture| {
+                save_texture(
+                    "resources/sobel.png",
+                    &output_texture,
+                    &self.wgpu.device,
+                    &self.wgpu.queue,
+                    0,
+                )
+            });
+            if let Err(e) = result {
+                self.report_error(&e);
+            }
             self.should_compute = false;
         }
     }
 
+    /// Uploads the vertices of `mesh` in `encoding` and logs the memory they take.
+    fn create_vertex_buffer(
+        mesh: &Mesh,
+        encoding: VertexEncoding,
+        device: &wgpu::Device,
+    ) -> VertexBuffer {
+        let vertex_buffer = match encoding {
+            VertexEncoding::Full => VertexBuffer::new(&mesh.vertices, device),
+            VertexEncoding::Packed => {
+                let packed: Vec<_> = mesh.vertices.iter().map(PackedVertex::new).collect();
+                VertexBuffer::new(&packed, device)
+            }
+        };
+        let full_size = mem::size_of_val(mesh.vertices.as_slice());
+        info!(
+            "{} vertices {encoding:?}: {} KiB, {:.0}% of the full encoding ({} KiB)",
+            vertex_buffer.vertex_count,
+            vertex_buffer.buffer.size() / 1024,
+            100.0 * vertex_buffer.buffer.size() as f32 / full_size.max(1) as f32,
+            full_size / 1024,
+        );
+        vertex_buffer
+    }
+
+    /// Group 0 of `shader.wgsl`.
+    fn create_bind_group(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        uniforms: &DataBuffer<Uniforms>,
+        light_uniforms: &DataBuffer<LightUniforms>,
+        lights: &StorageBuffer<LightData>,
+    ) -> Result<BindGroup> {
+        BindGroup::new(
+            device,
+            pipeline_cache,
+            &SHADER_BINDINGS,
+            0,
+            &[
+                BindResource::Buffer(&uniforms.buffer),
+                BindResource::Buffer(&light_uniforms.buffer),
+                BindResource::Buffer(&lights.buffer),
+            ],
+        )
+    }
+
+    /// Logs `error` and shows it in the GUI.
+    fn report_error(&mut self, error: &Error) {
+        error!("{error}");
+        self.gui_state.errors.push(error.to_string());
+    }
+
     fn resize(&mut self, new_size: PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.wgpu.resize(new_size.width, new_size.height);
-            self.depth_texture =
-                texture::Texture::depth(&self.wgpu.device, new_size.width, new_size.height);
+            self.hdr_texture =
+                Texture::hdr_target(&self.wgpu.device, new_size.width, new_size.height);
+            if let Err(e) = self.tonemap.set_input(
+                &self.wgpu.device,
+                &mut self.pipeline_cache,
+                &self.hdr_texture,
+            ) {
+                self.report_error(&e);
+            }
             let aspect = new_size.width as f32 / new_size.height as f32;
             self.uniforms.data.projection =
                 Mat4::perspective_lh(f32::to_radians(45.0), aspect, 0.01, 100.0);
@@ -348,7 +714,8 @@ impl ApplicationState {
         }
     }
 
-    fn key_input(&mut self, event: KeyCode) {}
+    #[allow(clippy::unused_self)]
+    const fn key_input(&self, _key: KeyCode) {}
 }
 
 #[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
@@ -367,28 +734,48 @@ struct Uniforms {
 
 #[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
-struct LightUniforms {
-    directions: [Vec4; 2],
-    colors: [Vec4; 2],
-    hardness: f32,
-    diffuse: f32,
-    specular: f32,
-    _padding: f32,
+pub struct LightUniforms {
+    /// Number of lights in the light storage buffer.
+    pub light_count: u32,
+    pub hardness: f32,
+    pub diffuse: f32,
+    pub specular: f32,
+    /// Index of the `ShadingModel` variant, set from `SceneDescriptor::shading_model`.
+    pub shading_model: u32,
+    /// Set from `SceneDescriptor::environment_intensity`.
+    pub environment_intensity: f32,
+    pub _padding: [u32; 2],
 }
 
 #[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 pub struct ComputeUniforms {
-    kernel: Mat3,
-    test: f32,
-    _pad: [f32; 6],
+    /// Columns of the kernel, WGSL aligns each column of a `mat3x3f` to 16 bytes.
+    pub kernel: [Vec4; 3],
+    pub test: f32,
+    pub _padding: [f32; 3],
 }
 
-#[derive(Clone, Copy, Default)]
-struct Camera {
-    orbit_radius: f32,
-    yaw: f32,
-    pitch: f32,
+impl ComputeUniforms {
+    pub fn new(kernel: Mat3, test: f32) -> Self {
+        Self {
+            kernel: [kernel.x_axis, kernel.y_axis, kernel.z_axis].map(|column| column.extend(0.0)),
+            test,
+            _padding: [0.0; 3],
+        }
+    }
+
+    pub fn kernel(&self) -> Mat3 {
+        let [x, y, z] = self.kernel.map(Vec4::truncate);
+        Mat3::from_cols(x, y, z)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Camera {
+    pub orbit_radius: f32,
+    pub yaw: f32,
+    pub pitch: f32,
 }
 
 impl Camera {
@@ -409,19 +796,19 @@ pub struct Application {
     event_loop: EventLoop<()>,
 }
 impl Application {
-    pub fn new() -> Self {
+    pub fn new(scene: &SceneDescriptor) -> Result<Self> {
         tracing_subscriber::fmt().init();
         let event_loop = EventLoop::new().unwrap();
         let window = Arc::new(WindowBuilder::new().build(&event_loop).unwrap());
 
         event_loop.set_control_flow(ControlFlow::Poll);
 
-        let state = ApplicationState::new(&window);
-        Self {
+        let state = ApplicationState::new(&window, scene)?;
+        Ok(Self {
             state,
             window,
             event_loop,
-        }
+        })
     }
     pub fn run(mut self) -> Result<(), winit::error::EventLoopError> {
         self.event_loop.run(move |event, elwt| match event {
@@ -429,7 +816,11 @@ impl Application {
                 event: window_event,
                 window_id,
             } if self.window.id() == window_id => {
-                let consumed = self.state.egui.handle_input(&self.window, &window_event);
+                let consumed = self
+                    .state
+                    .egui
+                    .as_mut()
+                    .is_some_and(|egui| egui.handle_input(&self.window, &window_event));
                 if consumed {
                     return;
                 }
@@ -462,3 +853,117 @@ impl Application {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::{
+        light::LightData, material::MaterialUniforms, reflection::assert_wgsl_layout,
+        shadow::ShadowUniforms, skybox::SkyboxUniforms, tonemap::TonemapUniforms,
+    };
+
+    const SHADER: &str = include_str!("shader.wgsl");
+
+    #[test]
+    fn scene_uniforms_match_shader_wgsl() {
+        assert_wgsl_layout!(
+            SHADER,
+            "Uniforms",
+            Uniforms {
+                model,
+                view,
+                projection,
+                color,
+                camera_world_position,
+                time,
+                normal_map_strength,
+                mip_level,
+            }
+        );
+        assert_wgsl_layout!(
+            SHADER,
+            "LightUniforms",
+            LightUniforms {
+                light_count,
+                hardness,
+                diffuse,
+                specular,
+                shading_model,
+                environment_intensity,
+            }
+        );
+        assert_wgsl_layout!(
+            SHADER,
+            "Light",
+            LightData {
+                position,
+                range,
+                direction,
+                kind,
+                color,
+                intensity,
+                inner_cone_cos,
+                outer_cone_cos,
+                shadow_layer,
+                shadow_cascades,
+            }
+        );
+        assert_wgsl_layout!(
+            SHADER,
+            "ShadowUniforms",
+            ShadowUniforms {
+                view_projections,
+                cascade_splits,
+                depth_bias,
+                normal_bias,
+                texel_size,
+                max_distance,
+            }
+        );
+        assert_wgsl_layout!(
+            SHADER,
+            "MaterialUniforms",
+            MaterialUniforms {
+                diffuse,
+                specular,
+                shininess,
+                metallic,
+                roughness,
+                occlusion_strength,
+                emissive,
+            }
+        );
+    }
+
+    #[test]
+    fn pass_uniforms_match_their_shaders() {
+        assert_wgsl_layout!(
+            include_str!("sobel.wgsl"),
+            "Uniforms",
+            ComputeUniforms { kernel, test }
+        );
+        assert_wgsl_layout!(
+            include_str!("skybox.wgsl"),
+            "SkyboxUniforms",
+            SkyboxUniforms {
+                inverse_view_projection,
+                intensity
+            }
+        );
+        assert_wgsl_layout!(
+            include_str!("tonemap.wgsl"),
+            "TonemapUniforms",
+            TonemapUniforms {
+                exposure,
+                tonemapping,
+                encode_srgb
+            }
+        );
+    }
+
+    #[test]
+    fn compute_kernel_round_trips() {
+        let kernel = Mat3::from_cols_array(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(ComputeUniforms::new(kernel, 0.5).kernel(), kernel);
+    }
+}
@@ -1,96 +1,104 @@
-use super::texture::Texture;
+use std::sync::Arc;
+
+use crate::error::Result;
+
+use super::{pipeline_cache::PipelineCache, reflection::ShaderReflection, texture::Texture};
 
 pub struct BindGroup {
-    pub(crate) bind_group_layout: wgpu::BindGroupLayout,
+    /// Shared by the bind groups of the same shader and group.
+    pub(crate) bind_group_layout: Arc<wgpu::BindGroupLayout>,
     pub(crate) bind_group: wgpu::BindGroup,
 }
 
+/// A resource of `BindGroup::new`, bound at the next free binding.
+#[derive(Clone, Copy)]
+pub enum BindResource<'a> {
+    Buffer(&'a wgpu::Buffer),
+    /// The view, and the sampler at the binding after it.
+    Texture(&'a Texture),
+}
+
 impl BindGroup {
+    /// Binds `resources` to `group` of `shader` in order, starting at binding 0.
+    ///
+    /// The layout is reflected from the shader, created once by `pipeline_cache`, and the
+    /// resources are checked against it.
+    ///
+    /// # Errors
+    /// If the resources do not match the bindings of the group.
     pub(crate) fn new(
         device: &wgpu::Device,
-        uniform_buffers: &[&wgpu::Buffer],
-        textures: &[&Texture],
-    ) -> Self {
-        let mut layout_entries = vec![];
-        let mut binding = 0;
-        for _ in uniform_buffers {
-            layout_entries.push(wgpu::BindGroupLayoutEntry {
-                binding,
-                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            });
-            binding += 1;
-        }
-
-        for _ in textures {
-            layout_entries.extend([
-                wgpu::BindGroupLayoutEntry {
-                    binding,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: binding + 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ]);
-            binding += 2;
-        }
-
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Uniform Bind Group Layout"),
-            entries: &layout_entries,
-        });
-        binding = 0;
+        pipeline_cache: &mut PipelineCache,
+        shader: &ShaderReflection,
+        group: u32,
+        resources: &[BindResource<'_>],
+    ) -> Result<Self> {
+        let mut binding_types = vec![];
         let mut bind_group_entries = vec![];
-
-        for uniforms in uniform_buffers {
-            bind_group_entries.push(wgpu::BindGroupEntry {
-                binding,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: uniforms,
-                    offset: 0,
-                    size: None,
-                }),
-            });
-            binding += 1;
-        }
-
-        for texture in textures {
-            bind_group_entries.extend([
-                wgpu::BindGroupEntry {
-                    binding,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: binding + 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ]);
-            binding += 2;
+        let mut binding = 0;
+        for resource in resources {
+            match resource {
+                BindResource::Buffer(buffer) => {
+                    let declared = shader.layout_entry(group, binding).map(|entry| entry.ty);
+                    binding_types.push((binding, buffer_binding_type(buffer, declared)));
+                    bind_group_entries.push(wgpu::BindGroupEntry {
+                        binding,
+                        resource: buffer.as_entire_binding(),
+                    });
+                    binding += 1;
+                }
+                BindResource::Texture(texture) => {
+                    let [view_type, sampler_type] = texture.binding_types();
+                    binding_types.extend([(binding, view_type), (binding + 1, sampler_type)]);
+                    bind_group_entries.extend([
+                        wgpu::BindGroupEntry {
+                            binding,
+                            resource: wgpu::BindingResource::TextureView(&texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: binding + 1,
+                            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                        },
+                    ]);
+                    binding += 2;
+                }
+            }
         }
+        shader.validate(group, &binding_types)?;
 
+        let bind_group_layout = pipeline_cache.bind_group_layout(device, shader, group);
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Uniform Bind Group Layout"),
-
+            label: Some(&format!("{} Group {group}", shader.name())),
             layout: &bind_group_layout,
             entries: &bind_group_entries,
         });
-        Self {
+        Ok(Self {
             bind_group_layout,
             bind_group,
-        }
+        })
+    }
+}
+
+/// Uniform buffers by their usage. Storage buffers can be bound with either access, they
+/// take the one `declared` by the shader, or read-write if it declares no storage buffer.
+fn buffer_binding_type(
+    buffer: &wgpu::Buffer,
+    declared: Option<wgpu::BindingType>,
+) -> wgpu::BindingType {
+    let read_only = matches!(
+        declared,
+        Some(wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            ..
+        })
+    );
+    wgpu::BindingType::Buffer {
+        ty: if buffer.usage().contains(wgpu::BufferUsages::UNIFORM) {
+            wgpu::BufferBindingType::Uniform
+        } else {
+            wgpu::BufferBindingType::Storage { read_only }
+        },
+        has_dynamic_offset: false,
+        min_binding_size: wgpu::BufferSize::new(buffer.size()),
     }
 }
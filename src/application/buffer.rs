@@ -2,55 +2,59 @@ use std::{fmt::Debug, mem};
 
 use wgpu::util::DeviceExt;
 
-pub struct VertexBuffer<A> {
-    pub(crate) vertices: Vec<A>,
-    // indices: Vec<u32>,
+/// Vertices of any layout, e.g. one of `VertexEncoding`.
+pub struct VertexBuffer {
+    pub(crate) vertex_count: usize,
     pub(crate) buffer: wgpu::Buffer,
 }
 
-impl<A> VertexBuffer<A>
-where
-    A: Debug + Clone + Copy + bytemuck::Pod + bytemuck::Zeroable,
-{
-    pub(crate) fn new(vertices: Vec<A>, device: &wgpu::Device) -> Self {
+impl VertexBuffer {
+    pub(crate) fn new<A>(vertices: &[A], device: &wgpu::Device) -> Self
+    where
+        A: Debug + Clone + Copy + bytemuck::Pod + bytemuck::Zeroable,
+    {
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertices),
+            contents: bytemuck::cast_slice(vertices),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
         });
-        // let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        //     label: Some("Index Buffer"),
-        //     contents: bytemuck::cast_slice(&indices),
-        //     usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::INDEX,
-        // });
         Self {
-            vertices,
-            // indices,
+            vertex_count: vertices.len(),
             buffer,
         }
     }
 }
+
 pub struct IndexBuffer {
-    pub(crate) indices: Vec<u32>,
-    // indices: Vec<u32>,
+    pub(crate) index_count: usize,
+    /// `Uint16` when every index fits, halving the buffer.
+    pub(crate) format: wgpu::IndexFormat,
     pub(crate) buffer: wgpu::Buffer,
 }
 
 impl IndexBuffer {
-    pub(crate) fn new(indices: Vec<u32>, device: &wgpu::Device) -> Self {
+    pub(crate) fn new(indices: &[u32], device: &wgpu::Device) -> Self {
+        let short_indices: Option<Vec<u16>> = indices
+            .iter()
+            .map(|&index| u16::try_from(index).ok())
+            .collect();
+        let (format, contents) = short_indices.as_deref().map_or_else(
+            || (wgpu::IndexFormat::Uint32, bytemuck::cast_slice(indices)),
+            |short_indices| {
+                (
+                    wgpu::IndexFormat::Uint16,
+                    bytemuck::cast_slice(short_indices),
+                )
+            },
+        );
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&indices),
+            label: Some("Index Buffer"),
+            contents,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::INDEX,
         });
-        // let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        //     label: Some("Index Buffer"),
-        //     contents: bytemuck::cast_slice(&indices),
-        //     usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::INDEX,
-        // });
         Self {
-            indices,
-            // indices,
+            index_count: indices.len(),
+            format,
             buffer,
         }
     }
@@ -67,7 +71,7 @@ impl<T> DataBuffer<T> {
         T: Debug + Clone + Copy + bytemuck::Pod + bytemuck::Zeroable,
     {
         assert!(
-            mem::align_of::<T>() % 4 == 0,
+            mem::align_of::<T>().is_multiple_of(4),
             "Data alignment needs to be multiple of 4"
         );
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -77,13 +81,14 @@ impl<T> DataBuffer<T> {
         });
         Self { data, buffer }
     }
+    #[allow(dead_code)]
     pub(crate) fn from_slice<U>(data: T, device: &wgpu::Device, usage: wgpu::BufferUsages) -> Self
     where
         U: Debug + Clone + Copy + bytemuck::Pod + bytemuck::Zeroable,
         T: AsRef<[U]>,
     {
         assert!(
-            mem::align_of::<T>() % 4 == 0,
+            mem::align_of::<T>().is_multiple_of(4),
             "Data alignment needs to be multiple of 4"
         );
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -113,6 +118,45 @@ impl<T> DataBuffer<T> {
     }
 }
 
+/// A read-only storage buffer holding a list of `T`, grown when the list does not fit.
+pub struct StorageBuffer<T> {
+    pub(crate) data: Vec<T>,
+    pub(crate) buffer: wgpu::Buffer,
+}
+
+impl<T> StorageBuffer<T>
+where
+    T: Debug + Clone + Copy + bytemuck::Pod + bytemuck::Zeroable,
+{
+    pub(crate) fn new(data: Vec<T>, device: &wgpu::Device) -> Self {
+        let buffer = Self::create_buffer(&data, device);
+        Self { data, buffer }
+    }
+
+    /// Uploads `data`. Returns `true` when the buffer had to be recreated, bind
+    /// groups using it must then be recreated too.
+    pub(crate) fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let capacity = self.buffer.size() as usize / mem::size_of::<T>();
+        if self.data.len() > capacity {
+            self.buffer = Self::create_buffer(&self.data, device);
+            return true;
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.data));
+        false
+    }
+
+    fn create_buffer(data: &[T], device: &wgpu::Device) -> wgpu::Buffer {
+        // Empty bindings are not allowed, keep room for one element.
+        let zeroed = [T::zeroed()];
+        let data = if data.is_empty() { &zeroed } else { data };
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Storage Buffer"),
+            contents: bytemuck::cast_slice(data),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        })
+    }
+}
+
 pub struct Buffer {
     pub(crate) buffer: wgpu::Buffer,
 }
@@ -129,12 +173,13 @@ where {
         Self { buffer }
     }
 
+    #[allow(dead_code)]
     pub fn initialize<T>(self, data: T, queue: &wgpu::Queue) -> DataBuffer<T>
     where
         T: Debug + Clone + Copy + bytemuck::Pod + bytemuck::Zeroable,
     {
         assert!(
-            mem::align_of::<T>() % 4 == 0,
+            mem::align_of::<T>().is_multiple_of(4),
             "Data alignment needs to be multiple of 4"
         );
 
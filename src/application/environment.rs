@@ -0,0 +1,470 @@
+use std::{
+    path::Path,
+    sync::{Arc, LazyLock},
+};
+
+use glam::Vec3;
+use image::{Rgba, Rgba32FImage};
+
+use crate::{
+    error::{validation_scope, Result},
+    resources::load_hdr,
+};
+
+use super::{
+    bind_group::{BindGroup, BindResource},
+    buffer::DataBuffer,
+    pipeline_cache::PipelineCache,
+    reflection::ShaderReflection,
+    texture::Texture,
+    SHADER_BINDINGS,
+};
+
+static BINDINGS: LazyLock<ShaderReflection> = LazyLock::new(|| {
+    ShaderReflection::from_wgsl("environment.wgsl", include_str!("../environment.wgsl"))
+        .expect("environment.wgsl is valid WGSL")
+});
+
+/// Radiance of the environment of scenes without one, a dim ambient light.
+pub const DEFAULT_AMBIENT: Vec3 = Vec3::splat(0.03);
+
+const MAX_CUBE_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Roughness goes from 0 at the first level to 1 at the last one, must match
+/// `PREFILTERED_MAX_LEVEL` in `shader.wgsl`. Cubemaps of the smallest size have them all.
+const PREFILTERED_MIP_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 128;
+const IRRADIANCE_SAMPLES: u32 = 1024;
+const SPECULAR_SAMPLES: u32 = 256;
+
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct Parameters {
+    face_size: u32,
+    sample_count: u32,
+    roughness: f32,
+    _padding: u32,
+}
+
+/// Image based lighting precomputed from an equirectangular radiance map.
+pub struct Environment {
+    /// Radiance in every direction, drawn by the skybox.
+    pub(crate) cubemap: Arc<Texture>,
+    // Kept alive as long as the bind group using them.
+    /// Cosine weighted radiance around each normal, for diffuse lighting.
+    _irradiance: Texture,
+    /// Radiance convolved with the GGX lobe, one roughness per mip level.
+    _prefiltered: Texture,
+    /// Split sum scale and bias of F0, by `NoV` and roughness.
+    _brdf_lut: Texture,
+    /// Bound at group 3 of `shader.wgsl`.
+    pub(crate) bind_group: BindGroup,
+}
+
+impl Environment {
+    /// Loads a `.hdr` equirectangular image.
+    pub(crate) fn load(
+        path: impl AsRef<Path>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Result<Self> {
+        Self::from_equirectangular(&load_hdr(path)?, device, queue, pipeline_cache)
+    }
+
+    /// The same radiance in every direction, e.g. a constant ambient term.
+    pub(crate) fn uniform(
+        radiance: Vec3,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Result<Self> {
+        let image = Rgba32FImage::from_pixel(1, 1, Rgba(radiance.extend(1.0).to_array()));
+        Self::from_equirectangular(&image, device, queue, pipeline_cache)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn from_equirectangular(
+        image: &Rgba32FImage,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Result<Self> {
+        validation_scope(device, || {
+            let equirectangular = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Equirectangular Environment"),
+                size: wgpu::Extent3d {
+                    width: image.width(),
+                    height: image.height(),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+            queue.write_texture(
+                equirectangular.as_image_copy(),
+                bytemuck::cast_slice(image.as_raw()),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(16 * image.width()),
+                    rows_per_image: Some(image.height()),
+                },
+                equirectangular.size(),
+            );
+
+            let cube_size = (image.width() / 4)
+                .next_power_of_two()
+                .clamp(16, MAX_CUBE_SIZE);
+            let cubemap = Texture::cube(device, "Environment Cubemap", cube_size, 1);
+            let irradiance = Texture::cube(
+                device,
+                "Irradiance Cubemap",
+                IRRADIANCE_SIZE.min(cube_size),
+                1,
+            );
+            let prefiltered_size = PREFILTERED_SIZE.min(cube_size);
+            let prefiltered = Texture::cube(
+                device,
+                "Prefiltered Cubemap",
+                prefiltered_size,
+                PREFILTERED_MIP_LEVELS,
+            );
+            let brdf_lut = Self::create_brdf_lut(device);
+
+            let passes = Passes::new(device, pipeline_cache);
+            // The cubemap is only written by copies between the passes reading it.
+            let sources = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Environment Sources Bind Group"),
+                layout: &passes.source_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(
+                            &equirectangular.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&cubemap.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+                    },
+                ],
+            });
+
+            let mut encoder =
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            let to_cube = passes.pipeline(device, pipeline_cache, "equirectangular_to_cube");
+            passes.draw_cube(
+                device,
+                &mut encoder,
+                &to_cube,
+                &sources,
+                &cubemap,
+                0,
+                Parameters::default(),
+            );
+            let convolve = passes.pipeline(device, pipeline_cache, "irradiance");
+            passes.draw_cube(
+                device,
+                &mut encoder,
+                &convolve,
+                &sources,
+                &irradiance,
+                0,
+                Parameters {
+                    sample_count: IRRADIANCE_SAMPLES,
+                    ..Default::default()
+                },
+            );
+            let prefilter = passes.pipeline(device, pipeline_cache, "prefilter");
+            let last_level = prefiltered.texture.mip_level_count() - 1;
+            for level in 0..=last_level {
+                let roughness = level as f32 / last_level as f32;
+                passes.draw_cube(
+                    device,
+                    &mut encoder,
+                    &prefilter,
+                    &sources,
+                    &prefiltered,
+                    level,
+                    Parameters {
+                        sample_count: SPECULAR_SAMPLES,
+                        roughness,
+                        ..Default::default()
+                    },
+                );
+            }
+            passes.draw_brdf_lut(device, pipeline_cache, &mut encoder, &brdf_lut);
+            queue.submit([encoder.finish()]);
+
+            let bind_group = Self::create_bind_group(
+                device,
+                pipeline_cache,
+                &irradiance,
+                &prefiltered,
+                &brdf_lut,
+            )?;
+            Ok(Self {
+                cubemap: Arc::new(cubemap),
+                _irradiance: irradiance,
+                _prefiltered: prefiltered,
+                _brdf_lut: brdf_lut,
+                bind_group,
+            })
+        })?
+    }
+
+    fn create_brdf_lut(device: &wgpu::Device) -> Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF LUT"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::HDR_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("BRDF LUT Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..wgpu::SamplerDescriptor::default()
+        });
+        Texture {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        irradiance: &Texture,
+        prefiltered: &Texture,
+        brdf_lut: &Texture,
+    ) -> Result<BindGroup> {
+        BindGroup::new(
+            device,
+            pipeline_cache,
+            &SHADER_BINDINGS,
+            3,
+            &[
+                BindResource::Texture(irradiance),
+                BindResource::Texture(prefiltered),
+                BindResource::Texture(brdf_lut),
+            ],
+        )
+    }
+}
+
+/// Compute passes of `environment.wgsl`.
+struct Passes {
+    shader: Arc<wgpu::ShaderModule>,
+    /// Group 0, the parameters and the written texture.
+    output_layout: Arc<wgpu::BindGroupLayout>,
+    /// Group 1, the equirectangular image and the environment cubemap.
+    source_layout: Arc<wgpu::BindGroupLayout>,
+}
+
+impl Passes {
+    fn new(device: &wgpu::Device, pipeline_cache: &mut PipelineCache) -> Self {
+        let shader = pipeline_cache.shader(device, wgpu::include_wgsl!("../environment.wgsl"));
+        Self {
+            shader,
+            output_layout: pipeline_cache.bind_group_layout(device, &BINDINGS, 0),
+            source_layout: pipeline_cache.bind_group_layout(device, &BINDINGS, 1),
+        }
+    }
+
+    fn pipeline(
+        &self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        entry_point: &str,
+    ) -> Arc<wgpu::ComputePipeline> {
+        pipeline_cache.compute_pipeline(
+            device,
+            &self.shader,
+            entry_point,
+            &[&self.output_layout, &self.source_layout],
+        )
+    }
+
+    fn output_bind_group(
+        &self,
+        device: &wgpu::Device,
+        parameters: &DataBuffer<Parameters>,
+        output: &wgpu::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Environment Output Bind Group"),
+            layout: &self.output_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: parameters.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &output.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+            ],
+        })
+    }
+
+    /// Writes mip `level` of `target` from the faces stacked in a scratch texture.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_cube(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        source: &wgpu::BindGroup,
+        target: &Texture,
+        level: u32,
+        parameters: Parameters,
+    ) {
+        let face_size = target
+            .texture
+            .size()
+            .mip_level_size(level, wgpu::TextureDimension::D2)
+            .width;
+        let scratch = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Cube Faces"),
+            size: wgpu::Extent3d {
+                width: face_size,
+                height: face_size * 6,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::HDR_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let parameters = DataBuffer::uniform(
+            Parameters {
+                face_size,
+                ..parameters
+            },
+            device,
+        );
+        let output = self.output_bind_group(device, &parameters, &scratch);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Environment Cube Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &output, &[]);
+        compute_pass.set_bind_group(1, source, &[]);
+        let workgroup_size_per_dim = 8;
+        compute_pass.dispatch_workgroups(
+            face_size.div_ceil(workgroup_size_per_dim),
+            (face_size * 6).div_ceil(workgroup_size_per_dim),
+            1,
+        );
+        drop(compute_pass);
+
+        for face in 0..6 {
+            encoder.copy_texture_to_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &scratch,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: face * face_size,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyTexture {
+                    texture: &target.texture,
+                    mip_level: level,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: face,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::Extent3d {
+                    width: face_size,
+                    height: face_size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    fn draw_brdf_lut(
+        &self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        encoder: &mut wgpu::CommandEncoder,
+        brdf_lut: &Texture,
+    ) {
+        let pipeline = pipeline_cache.compute_pipeline(
+            device,
+            &self.shader,
+            "brdf_lut",
+            &[&self.output_layout],
+        );
+        let parameters = DataBuffer::uniform(
+            Parameters {
+                face_size: BRDF_LUT_SIZE,
+                sample_count: SPECULAR_SAMPLES,
+                ..Default::default()
+            },
+            device,
+        );
+        let output = self.output_bind_group(device, &parameters, &brdf_lut.texture);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("BRDF LUT Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&pipeline);
+        compute_pass.set_bind_group(0, &output, &[]);
+        let workgroup_count = BRDF_LUT_SIZE.div_ceil(8);
+        compute_pass.dispatch_workgroups(workgroup_count, workgroup_count, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::reflection::assert_wgsl_layout;
+
+    #[test]
+    fn parameters_match_environment_wgsl() {
+        assert_wgsl_layout!(
+            include_str!("../environment.wgsl"),
+            "Parameters",
+            Parameters {
+                face_size,
+                sample_count,
+                roughness
+            }
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt::Display;
+
+use glam::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightKind {
+    #[default]
+    Directional,
+    Point,
+    Spot,
+}
+
+impl LightKind {
+    pub const ALL: [Self; 3] = [Self::Directional, Self::Point, Self::Spot];
+}
+
+impl Display for LightKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Directional => "Directional",
+            Self::Point => "Point",
+            Self::Spot => "Spot",
+        })
+    }
+}
+
+/// A light of the scene, converted to `LightData` for `shader.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,
+    /// Multiplies `color`, values above 1 are only visible after tonemapping.
+    pub intensity: f32,
+    /// Points towards the light, for spot lights from the center of the cone.
+    pub direction: Vec3,
+    /// Used by point and spot lights.
+    pub position: Vec3,
+    /// Distance at which point and spot lights fade out, 0 for no limit.
+    pub range: f32,
+    /// Half angles of spot cones in radians, the light fades out between them.
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            kind: LightKind::Directional,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            direction: Vec3::Y,
+            position: Vec3::Y,
+            range: 0.0,
+            inner_angle: 20_f32.to_radians(),
+            outer_angle: 30_f32.to_radians(),
+        }
+    }
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3) -> Self {
+        Self {
+            color,
+            direction,
+            ..Default::default()
+        }
+    }
+
+    pub fn data(&self) -> LightData {
+        LightData {
+            position: self.position,
+            range: self.range,
+            direction: self.direction.normalize_or_zero(),
+            kind: self.kind as u32,
+            color: self.color,
+            intensity: self.intensity,
+            inner_cone_cos: self.inner_angle.cos(),
+            outer_cone_cos: self.outer_angle.max(self.inner_angle).cos(),
+            shadow_layer: -1,
+            shadow_cascades: 0,
+        }
+    }
+}
+
+/// Layout of `Light` in `shader.wgsl`, aligned like its `vec3f` members.
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C, align(16))]
+pub struct LightData {
+    pub position: Vec3,
+    pub range: f32,
+    pub direction: Vec3,
+    pub kind: u32,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub inner_cone_cos: f32,
+    pub outer_cone_cos: f32,
+    /// First layer of the shadow map array used by this light, -1 without shadows.
+    pub shadow_layer: i32,
+    /// Number of layers starting at `shadow_layer`, split by view depth.
+    pub shadow_cascades: u32,
+}
@@ -0,0 +1,176 @@
+use std::{fmt::Display, sync::Arc};
+
+use glam::Vec4;
+use tracing::warn;
+
+use crate::{
+    error::{Error, Result},
+    resources::{ColorSpace, MaterialDescriptor, TextureSource},
+};
+
+use super::{
+    bind_group::{BindGroup, BindResource},
+    buffer::DataBuffer,
+    pipeline_cache::PipelineCache,
+    texture::{FallbackTextures, Texture},
+    wgpu_context::WgpuContext,
+    SHADER_BINDINGS,
+};
+
+/// Lighting model `shader.wgsl` evaluates for every material.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingModel {
+    /// Phong with the global hardness, diffuse and specular factors of `LightUniforms`.
+    #[default]
+    Phong,
+    /// Cook-Torrance GGX with metallic-roughness materials, as glTF assets expect.
+    Pbr,
+}
+
+impl ShadingModel {
+    pub const ALL: [Self; 2] = [Self::Phong, Self::Pbr];
+}
+
+impl Display for ShadingModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Phong => "Phong",
+            Self::Pbr => "PBR",
+        })
+    }
+}
+
+/// Textures and scalars of a material, bound at group 1 of `shader.wgsl`.
+pub struct Material {
+    // Shared with other materials, kept alive as long as the bind group using them.
+    #[allow(dead_code)]
+    pub(crate) albedo: Arc<Texture>,
+    #[allow(dead_code)]
+    pub(crate) normal: Arc<Texture>,
+    #[allow(dead_code)]
+    pub(crate) specular: Arc<Texture>,
+    #[allow(dead_code)]
+    pub(crate) metallic_roughness: Arc<Texture>,
+    #[allow(dead_code)]
+    pub(crate) occlusion: Arc<Texture>,
+    #[allow(dead_code)]
+    pub(crate) emissive: Arc<Texture>,
+    #[allow(dead_code)]
+    pub(crate) uniforms: DataBuffer<MaterialUniforms>,
+    pub(crate) bind_group: BindGroup,
+}
+
+impl Material {
+    /// Maps that are not set or fail to load use `fallbacks`, load errors are
+    /// passed to `on_error`.
+    pub(crate) fn new(
+        descriptor: &MaterialDescriptor,
+        fallbacks: &FallbackTextures,
+        wgpu: &WgpuContext,
+        pipeline_cache: &mut PipelineCache,
+        mut on_error: impl FnMut(Error),
+    ) -> Result<Self> {
+        let mut texture = |source: &Option<TextureSource>,
+                           color_space,
+                           unset: &Arc<Texture>,
+                           failed: &Arc<Texture>| {
+            let Some(source) = source else {
+                return unset.clone();
+            };
+            Texture::from_source(source, color_space, wgpu, pipeline_cache).map_or_else(
+                |e| {
+                    warn!(
+                        "Using fallback for texture {}: {e}",
+                        source.name().unwrap_or("<unnamed>")
+                    );
+                    on_error(e);
+                    failed.clone()
+                },
+                Arc::new,
+            )
+        };
+        let white = &fallbacks.white;
+        let albedo = texture(
+            &descriptor.diffuse_texture,
+            ColorSpace::Srgb,
+            white,
+            &fallbacks.checkerboard,
+        );
+        let normal = texture(
+            &descriptor.normal_texture,
+            ColorSpace::Linear,
+            &fallbacks.flat_normal,
+            &fallbacks.flat_normal,
+        );
+        let specular = texture(&descriptor.specular_texture, ColorSpace::Srgb, white, white);
+        let metallic_roughness = texture(
+            &descriptor.metallic_roughness_texture,
+            ColorSpace::Linear,
+            white,
+            white,
+        );
+        let occlusion = texture(
+            &descriptor.occlusion_texture,
+            ColorSpace::Linear,
+            white,
+            white,
+        );
+        let emissive = texture(
+            &descriptor.emissive_texture,
+            ColorSpace::Srgb,
+            white,
+            &fallbacks.black,
+        );
+
+        let uniforms = DataBuffer::uniform(
+            MaterialUniforms {
+                diffuse: descriptor.diffuse.extend(1.0),
+                specular: descriptor.specular.extend(1.0),
+                // Zero makes the shader fall back to the global hardness.
+                shininess: descriptor.shininess.unwrap_or(0.0),
+                metallic: descriptor.metallic,
+                roughness: descriptor.roughness,
+                occlusion_strength: descriptor.occlusion_strength,
+                emissive: descriptor.emissive.extend(1.0),
+            },
+            &wgpu.device,
+        );
+        let bind_group = BindGroup::new(
+            &wgpu.device,
+            pipeline_cache,
+            &SHADER_BINDINGS,
+            1,
+            &[
+                BindResource::Buffer(&uniforms.buffer),
+                BindResource::Texture(&albedo),
+                BindResource::Texture(&normal),
+                BindResource::Texture(&specular),
+                BindResource::Texture(&metallic_roughness),
+                BindResource::Texture(&occlusion),
+                BindResource::Texture(&emissive),
+            ],
+        )?;
+        Ok(Self {
+            albedo,
+            normal,
+            specular,
+            metallic_roughness,
+            occlusion,
+            emissive,
+            uniforms,
+            bind_group,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct MaterialUniforms {
+    pub diffuse: Vec4,
+    pub specular: Vec4,
+    pub shininess: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion_strength: f32,
+    pub emissive: Vec4,
+}
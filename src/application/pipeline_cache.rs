@@ -0,0 +1,139 @@
+use std::{collections::HashMap, sync::Arc};
+
+use super::{
+    reflection::ShaderReflection,
+    render_pipeline::{RenderPipeline, RenderPipelineBuilder, RenderPipelineKey},
+};
+
+/// Lookups of a `PipelineCache`, shown in the GUI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineCacheStats {
+    pub hits: u64,
+    /// Lookups that created a pipeline.
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ComputePipelineKey {
+    shader: wgpu::Id<wgpu::ShaderModule>,
+    entry_point: String,
+    bind_group_layouts: Vec<wgpu::Id<wgpu::BindGroupLayout>>,
+}
+
+/// Creates shader modules, bind group layouts and pipelines once and hands out the same
+/// objects for the same shader and state afterwards.
+#[derive(Default)]
+pub struct PipelineCache {
+    /// By label, e.g. the path given to `include_wgsl!`.
+    shaders: HashMap<String, Arc<wgpu::ShaderModule>>,
+    /// By reflected shader name and group.
+    bind_group_layouts: HashMap<(String, u32), Arc<wgpu::BindGroupLayout>>,
+    render_pipelines: HashMap<RenderPipelineKey, Arc<wgpu::RenderPipeline>>,
+    compute_pipelines: HashMap<ComputePipelineKey, Arc<wgpu::ComputePipeline>>,
+    pub(crate) stats: PipelineCacheStats,
+}
+
+impl PipelineCache {
+    /// The module created from `descriptor`, or the one created before with the same label.
+    ///
+    /// # Panics
+    /// If `descriptor` has no label, it identifies the shader.
+    pub(crate) fn shader(
+        &mut self,
+        device: &wgpu::Device,
+        descriptor: wgpu::ShaderModuleDescriptor<'_>,
+    ) -> Arc<wgpu::ShaderModule> {
+        let label = descriptor
+            .label
+            .expect("cached shader modules need a label")
+            .to_owned();
+        self.shaders
+            .entry(label)
+            .or_insert_with(|| Arc::new(device.create_shader_module(descriptor)))
+            .clone()
+    }
+
+    /// Layout of `group` of `shader`, shared by every bind group of that group.
+    pub(crate) fn bind_group_layout(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &ShaderReflection,
+        group: u32,
+    ) -> Arc<wgpu::BindGroupLayout> {
+        self.bind_group_layouts
+            .entry((shader.name().to_owned(), group))
+            .or_insert_with(|| {
+                Arc::new(
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some(&format!("{} Group {group} Layout", shader.name())),
+                        entries: &shader.layout_entries(group),
+                    }),
+                )
+            })
+            .clone()
+    }
+
+    /// Builds the pipeline of `builder` unless one with the same shader and state exists.
+    pub(crate) fn render_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        builder: RenderPipelineBuilder<'_>,
+    ) -> RenderPipeline {
+        let key = builder.key();
+        if let Some(render_pipeline) = self.render_pipelines.get(&key) {
+            self.stats.hits += 1;
+            return RenderPipeline {
+                render_pipeline: render_pipeline.clone(),
+            };
+        }
+        self.stats.misses += 1;
+        let pipeline = builder.build(device);
+        self.render_pipelines
+            .insert(key, pipeline.render_pipeline.clone());
+        pipeline
+    }
+
+    /// Compute pipeline of `entry_point` with `bind_group_layouts`, e.g. from
+    /// `bind_group_layout`.
+    ///
+    /// Without layouts, the layout is derived from `shader` and its bind group layouts
+    /// come from `wgpu::ComputePipeline::get_bind_group_layout`.
+    pub(crate) fn compute_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        entry_point: &str,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Arc<wgpu::ComputePipeline> {
+        let key = ComputePipelineKey {
+            shader: shader.global_id(),
+            entry_point: entry_point.to_owned(),
+            bind_group_layouts: bind_group_layouts
+                .iter()
+                .map(|layout| layout.global_id())
+                .collect(),
+        };
+        if let Some(compute_pipeline) = self.compute_pipelines.get(&key) {
+            self.stats.hits += 1;
+            return compute_pipeline.clone();
+        }
+        self.stats.misses += 1;
+        let layout = (!bind_group_layouts.is_empty()).then(|| {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(entry_point),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            })
+        });
+        let compute_pipeline = Arc::new(device.create_compute_pipeline(
+            &wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: layout.as_ref(),
+                module: shader,
+                entry_point,
+            },
+        ));
+        self.compute_pipelines.insert(key, compute_pipeline.clone());
+        compute_pipeline
+    }
+}
@@ -0,0 +1,682 @@
+use std::num::NonZeroU64;
+
+use naga::{
+    valid::{Capabilities, ValidationFlags, Validator},
+    AddressSpace, ArraySize, ImageClass, ImageDimension, ScalarKind, StorageAccess, StorageFormat,
+    TypeInner,
+};
+
+use crate::error::{Error, Result};
+
+/// A resource binding declared by a shader.
+#[derive(Debug, Clone)]
+struct ReflectedBinding {
+    group: u32,
+    /// Name of the global variable, for error messages.
+    variable: String,
+    entry: wgpu::BindGroupLayoutEntry,
+}
+
+/// Resource bindings of a WGSL module, read with naga.
+///
+/// Float textures the shader samples with a sampler are assumed to be filterable, the shader
+/// cannot tell. Those it only loads from are not, so 32-bit float formats can be bound.
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    name: String,
+    bindings: Vec<ReflectedBinding>,
+}
+
+impl ShaderReflection {
+    /// Parses and validates `source`, `name` identifies the shader in errors.
+    ///
+    /// # Errors
+    /// If naga rejects the module or a binding has no wgpu equivalent.
+    pub fn from_wgsl(name: &str, source: &str) -> Result<Self> {
+        let shader_error = |message| Error::Shader {
+            name: name.to_owned(),
+            message,
+        };
+        let module = parse(name, source)?;
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|e| shader_error(e.emit_to_string(source)))?;
+
+        let mut bindings = vec![];
+        for (handle, global) in module.global_variables.iter() {
+            let Some(binding) = &global.binding else {
+                continue;
+            };
+            let variable = global.name.clone().unwrap_or_default();
+            let visibility = module
+                .entry_points
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| !info.get_entry_point(i)[handle].is_empty())
+                .fold(wgpu::ShaderStages::NONE, |stages, (_, entry_point)| {
+                    stages | shader_stage(entry_point.stage)
+                });
+            let sampled = (0..module.entry_points.len()).any(|i| {
+                info.get_entry_point(i)
+                    .sampling_set
+                    .iter()
+                    .any(|key| key.image == handle)
+            });
+            let (ty, count) = binding_type(&module, global, sampled).map_err(|message| {
+                shader_error(format!(
+                    "group {} binding {} ({variable}): {message}",
+                    binding.group, binding.binding
+                ))
+            })?;
+            bindings.push(ReflectedBinding {
+                group: binding.group,
+                variable,
+                entry: wgpu::BindGroupLayoutEntry {
+                    binding: binding.binding,
+                    visibility,
+                    ty,
+                    count,
+                },
+            });
+        }
+        bindings.sort_by_key(|binding| (binding.group, binding.entry.binding));
+        Ok(Self {
+            name: name.to_owned(),
+            bindings,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Entries of the layout of `group`, visible to the stages that use them.
+    pub fn layout_entries(&self, group: u32) -> Vec<wgpu::BindGroupLayoutEntry> {
+        self.group(group).map(|binding| binding.entry).collect()
+    }
+
+    /// Entry of `binding` in the layout of `group`, if the shader declares it.
+    pub fn layout_entry(&self, group: u32, binding: u32) -> Option<wgpu::BindGroupLayoutEntry> {
+        self.group(group)
+            .find(|reflected| reflected.entry.binding == binding)
+            .map(|reflected| reflected.entry)
+    }
+
+    /// Checks that `resources`, binding indices with the type each resource can be
+    /// bound as, cover `group` exactly.
+    ///
+    /// Buffers are described by their usage and size, e.g. `Storage { read_only: false }`
+    /// can be bound read-only and `min_binding_size` is the size of the buffer.
+    ///
+    /// # Errors
+    /// If a binding of the shader has no resource or one of another type, or a
+    /// resource has no binding.
+    pub fn validate(&self, group: u32, resources: &[(u32, wgpu::BindingType)]) -> Result<()> {
+        for binding in self.group(group) {
+            let index = binding.entry.binding;
+            let expected = describe(&binding.entry.ty);
+            let Some((_, ty)) = resources.iter().find(|(i, _)| *i == index) else {
+                return Err(self.error(format!(
+                    "group {group} binding {index} ({}) expects {expected}, nothing is bound",
+                    binding.variable
+                )));
+            };
+            if !is_compatible(&binding.entry.ty, ty) {
+                return Err(self.error(format!(
+                    "group {group} binding {index} ({}) expects {expected}, got {}",
+                    binding.variable,
+                    describe(ty)
+                )));
+            }
+        }
+        if let Some((index, ty)) = resources
+            .iter()
+            .find(|(i, _)| !self.group(group).any(|binding| binding.entry.binding == *i))
+        {
+            return Err(self.error(format!(
+                "group {group} binding {index} is not declared, got {}",
+                describe(ty)
+            )));
+        }
+        Ok(())
+    }
+
+    fn group(&self, group: u32) -> impl Iterator<Item = &ReflectedBinding> {
+        self.bindings
+            .iter()
+            .filter(move |binding| binding.group == group)
+    }
+
+    fn error(&self, message: String) -> Error {
+        Error::Shader {
+            name: self.name.clone(),
+            message,
+        }
+    }
+}
+
+fn parse(name: &str, source: &str) -> Result<naga::Module> {
+    naga::front::wgsl::parse_str(source).map_err(|e| Error::Shader {
+        name: name.to_owned(),
+        message: e.emit_to_string(source),
+    })
+}
+
+/// Size, alignment and member offsets of a WGSL struct in uniform and storage buffers.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructLayout {
+    pub size: u32,
+    pub alignment: u32,
+    /// Names and offsets, in declaration order.
+    pub members: Vec<(String, u32)>,
+}
+
+/// Layout of the struct `name` declared in `source`.
+///
+/// # Errors
+/// If naga rejects `source`, it has no struct `name` or the struct cannot be laid out.
+#[cfg(test)]
+pub fn struct_layout(source: &str, name: &str) -> Result<StructLayout> {
+    let shader_error = |message| Error::Shader {
+        name: name.to_owned(),
+        message,
+    };
+    let module = parse(name, source)?;
+    let mut layouter = naga::proc::Layouter::default();
+    layouter
+        .update(module.to_ctx())
+        .map_err(|e| shader_error(e.to_string()))?;
+    let (handle, members) = module
+        .types
+        .iter()
+        .find_map(|(handle, ty)| match &ty.inner {
+            TypeInner::Struct { members, .. } if ty.name.as_deref() == Some(name) => {
+                Some((handle, members))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| shader_error("no struct of that name".to_owned()))?;
+    Ok(StructLayout {
+        size: layouter[handle].size,
+        alignment: layouter[handle].alignment.round_up(1),
+        members: members
+            .iter()
+            .map(|member| (member.name.clone().unwrap_or_default(), member.offset))
+            .collect(),
+    })
+}
+
+/// Panics unless a `#[repr(C)]` struct with `size`, `alignment` and `fields` is laid out
+/// like the WGSL struct `name` in `source`, see `assert_wgsl_layout!`.
+///
+/// Fields are named like the WGSL members, padding fields are left out. The Rust struct
+/// may end in padding the WGSL struct does not declare, up to the alignment of the WGSL
+/// struct in a uniform buffer, i.e. rounded up to 16 bytes, which is the size downlevel
+/// backends bind uniform buffers in. Structs read as arrays from storage buffers declare
+/// no such padding, so their strides stay equal.
+#[cfg(test)]
+pub fn assert_struct_layout(
+    source: &str,
+    name: &str,
+    size: usize,
+    alignment: usize,
+    fields: &[(&str, usize)],
+) {
+    let layout = struct_layout(source, name).unwrap_or_else(|e| panic!("{e}"));
+    let member_names: Vec<_> = layout
+        .members
+        .iter()
+        .map(|(member, _)| member.as_str())
+        .collect();
+    let field_names: Vec<_> = fields.iter().map(|&(field, _)| field).collect();
+    assert_eq!(field_names, member_names, "fields of {name}");
+    for ((field, offset), (_, member_offset)) in fields.iter().zip(&layout.members) {
+        assert_eq!(
+            *offset, *member_offset as usize,
+            "offset of {name}::{field}"
+        );
+    }
+    let padded_size = layout.size.next_multiple_of(layout.alignment.max(16));
+    assert!(
+        (layout.size..=padded_size).contains(&(size as u32)),
+        "size of {name} is {size}, expected {} up to {padded_size}",
+        layout.size
+    );
+    assert_eq!(alignment, layout.alignment as usize, "alignment of {name}");
+}
+
+/// Asserts that a Rust struct matches the layout of a WGSL struct, e.g.
+/// `assert_wgsl_layout!(include_str!("shader.wgsl"), "Light", LightData { position, range })`
+/// with every member of `Light` listed.
+#[cfg(test)]
+macro_rules! assert_wgsl_layout {
+    ($source:expr, $name:literal, $ty:ty { $($field:ident),+ $(,)? }) => {
+        $crate::application::reflection::assert_struct_layout(
+            $source,
+            $name,
+            ::std::mem::size_of::<$ty>(),
+            ::std::mem::align_of::<$ty>(),
+            &[$((stringify!($field), ::std::mem::offset_of!($ty, $field))),+],
+        )
+    };
+}
+#[cfg(test)]
+pub(crate) use assert_wgsl_layout;
+
+const fn shader_stage(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+    }
+}
+
+/// Layout type and array count of `global`, or why it cannot be bound.
+fn binding_type(
+    module: &naga::Module,
+    global: &naga::GlobalVariable,
+    sampled: bool,
+) -> Result<(wgpu::BindingType, Option<std::num::NonZeroU32>), String> {
+    let mut inner = &module.types[global.ty].inner;
+    let mut count = None;
+    if let TypeInner::BindingArray { base, size } = *inner {
+        let ArraySize::Constant(size) = size else {
+            return Err("binding arrays need a constant size".to_owned());
+        };
+        count = Some(size);
+        inner = &module.types[base].inner;
+    }
+    let buffer = |ty| wgpu::BindingType::Buffer {
+        ty,
+        has_dynamic_offset: false,
+        // A runtime sized array counts with a single element.
+        min_binding_size: NonZeroU64::new(u64::from(inner.size(module.to_ctx()))),
+    };
+    let ty = match global.space {
+        AddressSpace::Uniform => buffer(wgpu::BufferBindingType::Uniform),
+        AddressSpace::Storage { access } => buffer(wgpu::BufferBindingType::Storage {
+            read_only: !access.contains(StorageAccess::STORE),
+        }),
+        AddressSpace::Handle => match *inner {
+            TypeInner::Sampler { comparison } => wgpu::BindingType::Sampler(if comparison {
+                wgpu::SamplerBindingType::Comparison
+            } else {
+                wgpu::SamplerBindingType::Filtering
+            }),
+            TypeInner::Image {
+                dim,
+                arrayed,
+                class,
+            } => image_binding_type(dim, arrayed, class, sampled)?,
+            _ => return Err("handles must be textures or samplers".to_owned()),
+        },
+        space => return Err(format!("{space:?} variables cannot be bound")),
+    };
+    Ok((ty, count))
+}
+
+fn image_binding_type(
+    dim: ImageDimension,
+    arrayed: bool,
+    class: ImageClass,
+    sampled: bool,
+) -> Result<wgpu::BindingType, String> {
+    let view_dimension = match (dim, arrayed) {
+        (ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+        (ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+        (ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+        (ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+        (ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+        (ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+        (dim, true) => return Err(format!("{dim:?} textures cannot be arrayed")),
+    };
+    Ok(match class {
+        ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+            sample_type: match kind {
+                // Multisampled textures cannot be filtered.
+                ScalarKind::Float => wgpu::TextureSampleType::Float {
+                    filterable: sampled && !multi,
+                },
+                ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                kind => return Err(format!("textures cannot sample {kind:?}")),
+            },
+            view_dimension,
+            multisampled: multi,
+        },
+        ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Depth,
+            view_dimension,
+            multisampled: multi,
+        },
+        ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+            access: match (
+                access.contains(StorageAccess::LOAD),
+                access.contains(StorageAccess::STORE),
+            ) {
+                (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                (true, false) => wgpu::StorageTextureAccess::ReadOnly,
+                (false, _) => wgpu::StorageTextureAccess::WriteOnly,
+            },
+            format: storage_format(format),
+            view_dimension,
+        },
+    })
+}
+
+const fn storage_format(format: StorageFormat) -> wgpu::TextureFormat {
+    use wgpu::TextureFormat as Tf;
+    match format {
+        StorageFormat::R8Unorm => Tf::R8Unorm,
+        StorageFormat::R8Snorm => Tf::R8Snorm,
+        StorageFormat::R8Uint => Tf::R8Uint,
+        StorageFormat::R8Sint => Tf::R8Sint,
+        StorageFormat::R16Uint => Tf::R16Uint,
+        StorageFormat::R16Sint => Tf::R16Sint,
+        StorageFormat::R16Float => Tf::R16Float,
+        StorageFormat::Rg8Unorm => Tf::Rg8Unorm,
+        StorageFormat::Rg8Snorm => Tf::Rg8Snorm,
+        StorageFormat::Rg8Uint => Tf::Rg8Uint,
+        StorageFormat::Rg8Sint => Tf::Rg8Sint,
+        StorageFormat::R32Uint => Tf::R32Uint,
+        StorageFormat::R32Sint => Tf::R32Sint,
+        StorageFormat::R32Float => Tf::R32Float,
+        StorageFormat::Rg16Uint => Tf::Rg16Uint,
+        StorageFormat::Rg16Sint => Tf::Rg16Sint,
+        StorageFormat::Rg16Float => Tf::Rg16Float,
+        StorageFormat::Rgba8Unorm => Tf::Rgba8Unorm,
+        StorageFormat::Rgba8Snorm => Tf::Rgba8Snorm,
+        StorageFormat::Rgba8Uint => Tf::Rgba8Uint,
+        StorageFormat::Rgba8Sint => Tf::Rgba8Sint,
+        StorageFormat::Bgra8Unorm => Tf::Bgra8Unorm,
+        StorageFormat::Rgb10a2Uint => Tf::Rgb10a2Uint,
+        StorageFormat::Rgb10a2Unorm => Tf::Rgb10a2Unorm,
+        StorageFormat::Rg11b10Float => Tf::Rg11b10Float,
+        StorageFormat::Rg32Uint => Tf::Rg32Uint,
+        StorageFormat::Rg32Sint => Tf::Rg32Sint,
+        StorageFormat::Rg32Float => Tf::Rg32Float,
+        StorageFormat::Rgba16Uint => Tf::Rgba16Uint,
+        StorageFormat::Rgba16Sint => Tf::Rgba16Sint,
+        StorageFormat::Rgba16Float => Tf::Rgba16Float,
+        StorageFormat::Rgba32Uint => Tf::Rgba32Uint,
+        StorageFormat::Rgba32Sint => Tf::Rgba32Sint,
+        StorageFormat::Rgba32Float => Tf::Rgba32Float,
+        StorageFormat::R16Unorm => Tf::R16Unorm,
+        StorageFormat::R16Snorm => Tf::R16Snorm,
+        StorageFormat::Rg16Unorm => Tf::Rg16Unorm,
+        StorageFormat::Rg16Snorm => Tf::Rg16Snorm,
+        StorageFormat::Rgba16Unorm => Tf::Rgba16Unorm,
+        StorageFormat::Rgba16Snorm => Tf::Rgba16Snorm,
+    }
+}
+
+/// Whether a resource bound as `actual` satisfies a layout entry of type `expected`.
+fn is_compatible(expected: &wgpu::BindingType, actual: &wgpu::BindingType) -> bool {
+    use wgpu::{BindingType, BufferBindingType, SamplerBindingType, TextureSampleType};
+    match (expected, actual) {
+        (
+            BindingType::Buffer {
+                ty: expected_ty,
+                min_binding_size,
+                ..
+            },
+            BindingType::Buffer {
+                ty: actual_ty,
+                min_binding_size: size,
+                ..
+            },
+        ) => {
+            matches!(
+                (expected_ty, actual_ty),
+                (BufferBindingType::Uniform, BufferBindingType::Uniform)
+                    | (
+                        BufferBindingType::Storage { read_only: true },
+                        BufferBindingType::Storage { .. }
+                    )
+                    | (
+                        BufferBindingType::Storage { read_only: false },
+                        BufferBindingType::Storage { read_only: false }
+                    )
+            ) && min_binding_size
+                .zip(*size)
+                .is_none_or(|(min, size)| size >= min)
+        }
+        (
+            BindingType::Texture {
+                sample_type: expected_sample_type,
+                view_dimension: expected_dimension,
+                multisampled: expected_multisampled,
+            },
+            BindingType::Texture {
+                sample_type,
+                view_dimension,
+                multisampled,
+            },
+        ) => {
+            expected_dimension == view_dimension
+                && expected_multisampled == multisampled
+                && matches!(
+                    (expected_sample_type, sample_type),
+                    (
+                        TextureSampleType::Float { filterable: false },
+                        TextureSampleType::Float { .. } | TextureSampleType::Depth
+                    ) | (
+                        TextureSampleType::Float { filterable: true },
+                        TextureSampleType::Float { filterable: true }
+                    ) | (TextureSampleType::Depth, TextureSampleType::Depth)
+                        | (TextureSampleType::Sint, TextureSampleType::Sint)
+                        | (TextureSampleType::Uint, TextureSampleType::Uint)
+                )
+        }
+        (BindingType::Sampler(expected), BindingType::Sampler(actual)) => {
+            expected == actual
+                || (*expected == SamplerBindingType::Filtering
+                    && *actual == SamplerBindingType::NonFiltering)
+        }
+        (BindingType::StorageTexture { .. }, BindingType::StorageTexture { .. }) => {
+            expected == actual
+        }
+        _ => false,
+    }
+}
+
+/// `ty` in WGSL terms, for error messages.
+fn describe(ty: &wgpu::BindingType) -> String {
+    match ty {
+        wgpu::BindingType::Buffer {
+            ty,
+            min_binding_size,
+            ..
+        } => {
+            let space = match ty {
+                wgpu::BufferBindingType::Uniform => "var<uniform>",
+                wgpu::BufferBindingType::Storage { read_only: true } => "var<storage, read>",
+                wgpu::BufferBindingType::Storage { read_only: false } => "var<storage, read_write>",
+            };
+            min_binding_size.map_or_else(
+                || space.to_owned(),
+                |size| format!("{space} of {size} bytes"),
+            )
+        }
+        wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension,
+            multisampled,
+        } => {
+            let multisampled = if *multisampled { "multisampled " } else { "" };
+            format!("{multisampled}{view_dimension:?} texture of {sample_type:?}")
+        }
+        wgpu::BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension,
+        } => format!("{access:?} {view_dimension:?} storage texture of {format:?}"),
+        wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison) => {
+            "sampler_comparison".to_owned()
+        }
+        wgpu::BindingType::Sampler(_) => "sampler".to_owned(),
+        wgpu::BindingType::AccelerationStructure => "acceleration structure".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texture(view_dimension: wgpu::TextureViewDimension) -> wgpu::BindingType {
+        wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension,
+            multisampled: false,
+        }
+    }
+
+    fn uniform(size: u64) -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(size),
+        }
+    }
+
+    const SAMPLER: wgpu::BindingType =
+        wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering);
+
+    fn skybox() -> ShaderReflection {
+        ShaderReflection::from_wgsl("skybox.wgsl", include_str!("../skybox.wgsl")).unwrap()
+    }
+
+    #[test]
+    fn shader_bindings_are_reflected() {
+        let shader =
+            ShaderReflection::from_wgsl("shader.wgsl", include_str!("../shader.wgsl")).unwrap();
+        let scene = shader.layout_entries(0);
+        assert_eq!(scene.len(), 3);
+        assert!(scene[0]
+            .visibility
+            .contains(wgpu::ShaderStages::VERTEX_FRAGMENT));
+        assert!(matches!(
+            scene[2].ty,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                ..
+            }
+        ));
+        assert_eq!(shader.layout_entries(1).len(), 13);
+        let shadows = shader.layout_entries(2);
+        assert_eq!(
+            shadows[1].ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                multisampled: false,
+            }
+        );
+        assert_eq!(
+            shadows[2].ty,
+            wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison)
+        );
+        assert_eq!(
+            shader.layout_entries(3)[0].ty,
+            texture(wgpu::TextureViewDimension::Cube)
+        );
+    }
+
+    #[test]
+    fn storage_textures_and_compute_visibility() {
+        let shader =
+            ShaderReflection::from_wgsl("sobel.wgsl", include_str!("../sobel.wgsl")).unwrap();
+        let entries = shader.layout_entries(0);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.visibility == wgpu::ShaderStages::COMPUTE));
+        // Only loaded from, never sampled.
+        assert_eq!(
+            entries[0].ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            }
+        );
+        assert_eq!(
+            entries[1].ty,
+            wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            }
+        );
+    }
+
+    #[test]
+    fn matching_resources_validate() {
+        let shader = skybox();
+        let wgpu::BindingType::Buffer {
+            min_binding_size: Some(size),
+            ..
+        } = shader.layout_entries(0)[0].ty
+        else {
+            panic!("binding 0 is a uniform buffer");
+        };
+        let resources = [
+            (0, uniform(size.get() + 16)),
+            (1, texture(wgpu::TextureViewDimension::Cube)),
+            (2, SAMPLER),
+        ];
+        shader.validate(0, &resources).unwrap();
+    }
+
+    #[test]
+    fn mismatched_resources_are_reported() {
+        let shader = skybox();
+        let message = |resources: &[(u32, wgpu::BindingType)]| {
+            shader.validate(0, resources).unwrap_err().to_string()
+        };
+        let cube = (1, texture(wgpu::TextureViewDimension::Cube));
+
+        let wrong_dimension = message(&[
+            (0, uniform(256)),
+            (1, texture(wgpu::TextureViewDimension::D2)),
+            (2, SAMPLER),
+        ]);
+        assert!(
+            wrong_dimension.contains("binding 1 (environment)"),
+            "{wrong_dimension}"
+        );
+        assert!(
+            wrong_dimension.contains("got D2 texture"),
+            "{wrong_dimension}"
+        );
+
+        let too_small = message(&[(0, uniform(4)), cube, (2, SAMPLER)]);
+        assert!(too_small.contains("of 4 bytes"), "{too_small}");
+
+        let missing = message(&[(0, uniform(256)), cube]);
+        assert!(missing.contains("nothing is bound"), "{missing}");
+
+        let extra = message(&[(0, uniform(256)), cube, (2, SAMPLER), (3, SAMPLER)]);
+        assert!(extra.contains("binding 3 is not declared"), "{extra}");
+    }
+
+    #[test]
+    fn struct_layout_pads_like_wgsl() {
+        let layout = struct_layout(include_str!("../sobel.wgsl"), "Uniforms").unwrap();
+        assert_eq!(
+            layout,
+            StructLayout {
+                size: 64,
+                alignment: 16,
+                members: vec![("kernel".to_owned(), 0), ("test".to_owned(), 48)],
+            }
+        );
+        assert!(struct_layout(include_str!("../sobel.wgsl"), "Missing").is_err());
+    }
+
+    #[test]
+    fn invalid_wgsl_is_an_error() {
+        let error = ShaderReflection::from_wgsl("broken.wgsl", "fn main( {").unwrap_err();
+        assert!(matches!(error, Error::Shader { name, .. } if name == "broken.wgsl"));
+    }
+}
@@ -1,78 +1,219 @@
-use std::fmt::Debug;
+use std::sync::Arc;
 
 use crate::resources::VertexAttributeLayout;
 
 pub struct RenderPipeline {
-    pub(crate) render_pipeline: wgpu::RenderPipeline,
+    /// Shared with the `PipelineCache` that created it, if any.
+    pub(crate) render_pipeline: Arc<wgpu::RenderPipeline>,
 }
 
 impl RenderPipeline {
-    pub(crate) fn new<T>(
-        device: &wgpu::Device,
-        bind_group_layout: &wgpu::BindGroupLayout,
-        depth_texture_format: wgpu::TextureFormat,
-        surface_format: wgpu::TextureFormat,
-        shader: wgpu::ShaderModuleDescriptor,
-    ) -> Self
-    where
-        T: Debug + Clone + Copy + bytemuck::Pod + bytemuck::Zeroable + VertexAttributeLayout,
-    {
-        let shader = device.create_shader_module(shader);
+    /// Starts a pipeline drawing a triangle list with `vs_main` and `fs_main` of
+    /// `shader`, without culling, depth testing or color targets.
+    pub(crate) fn builder<'a>(
+        label: &'a str,
+        shader: &'a wgpu::ShaderModule,
+    ) -> RenderPipelineBuilder<'a> {
+        RenderPipelineBuilder {
+            label,
+            shader,
+            vertex_entry_point: "vs_main",
+            fragment_entry_point: Some("fs_main"),
+            vertex_buffers: vec![],
+            bind_group_layouts: vec![],
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            color_targets: vec![],
+            multisample: wgpu::MultisampleState::default(),
+        }
+    }
+}
+
+/// Everything a `RenderPipelineBuilder` builds from, the label aside.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RenderPipelineKey {
+    shader: wgpu::Id<wgpu::ShaderModule>,
+    vertex_entry_point: String,
+    fragment_entry_point: Option<String>,
+    vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+    bind_group_layouts: Vec<wgpu::Id<wgpu::BindGroupLayout>>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    color_targets: Vec<Option<wgpu::ColorTargetState>>,
+    multisample: wgpu::MultisampleState,
+}
+
+/// State of a `RenderPipeline`, see `RenderPipeline::builder`.
+pub struct RenderPipelineBuilder<'a> {
+    label: &'a str,
+    shader: &'a wgpu::ShaderModule,
+    vertex_entry_point: &'a str,
+    /// `None` for depth only pipelines, e.g. shadow maps.
+    fragment_entry_point: Option<&'a str>,
+    vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+    bind_group_layouts: Vec<&'a wgpu::BindGroupLayout>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    color_targets: Vec<Option<wgpu::ColorTargetState>>,
+    multisample: wgpu::MultisampleState,
+}
+
+// Not every state is used by the pipelines of the application.
+#[allow(dead_code)]
+impl<'a> RenderPipelineBuilder<'a> {
+    pub(crate) const fn vertex_entry_point(mut self, entry_point: &'a str) -> Self {
+        self.vertex_entry_point = entry_point;
+        self
+    }
+
+    /// Without a fragment stage only depth is written.
+    pub(crate) const fn fragment_entry_point(mut self, entry_point: Option<&'a str>) -> Self {
+        self.fragment_entry_point = entry_point;
+        self
+    }
+
+    /// Adds a vertex buffer slot laid out like `T`.
+    pub(crate) fn vertex_buffer<T: VertexAttributeLayout>(self) -> Self {
+        self.vertex_buffer_layout(T::layout())
+    }
+
+    /// Adds a vertex buffer slot with a layout chosen at runtime.
+    pub(crate) fn vertex_buffer_layout(
+        mut self,
+        layout: wgpu::VertexBufferLayout<'static>,
+    ) -> Self {
+        self.vertex_buffers.push(layout);
+        self
+    }
+
+    /// Layouts of the bind groups, in group order.
+    pub(crate) fn bind_group_layouts(mut self, layouts: &[&'a wgpu::BindGroupLayout]) -> Self {
+        self.bind_group_layouts = layouts.to_vec();
+        self
+    }
+
+    pub(crate) const fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        self
+    }
+
+    pub(crate) const fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.primitive.cull_mode = cull_mode;
+        self
+    }
 
+    pub(crate) const fn front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+        self.primitive.front_face = front_face;
+        self
+    }
+
+    /// Anything but `Fill` needs the matching device feature, e.g. `POLYGON_MODE_LINE`.
+    pub(crate) const fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.primitive.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// Tests against a depth target of `format`, without stencil or bias.
+    pub(crate) fn depth(
+        mut self,
+        format: wgpu::TextureFormat,
+        write_enabled: bool,
+        compare: wgpu::CompareFunction,
+    ) -> Self {
+        self.depth_stencil = Some(wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: write_enabled,
+            depth_compare: compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        });
+        self
+    }
+
+    /// Only applies after `depth`.
+    pub(crate) const fn depth_bias(mut self, bias: wgpu::DepthBiasState) -> Self {
+        if let Some(depth_stencil) = &mut self.depth_stencil {
+            depth_stencil.bias = bias;
+        }
+        self
+    }
+
+    /// Only applies after `depth`, the depth format must have a stencil aspect.
+    pub(crate) const fn stencil(mut self, stencil: wgpu::StencilState) -> Self {
+        if let Some(depth_stencil) = &mut self.depth_stencil {
+            depth_stencil.stencil = stencil;
+        }
+        self
+    }
+
+    /// Adds a color target at the next location, `blend` replaces its content when `None`.
+    pub(crate) fn color_target(
+        mut self,
+        format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
+    ) -> Self {
+        self.color_targets.push(Some(wgpu::ColorTargetState {
+            format,
+            blend,
+            write_mask: wgpu::ColorWrites::all(),
+        }));
+        self
+    }
+
+    /// MSAA sample count, attachments must have the same.
+    pub(crate) const fn sample_count(mut self, count: u32) -> Self {
+        self.multisample.count = count;
+        self
+    }
+
+    /// Identifies the pipeline in a `PipelineCache`.
+    pub(crate) fn key(&self) -> RenderPipelineKey {
+        RenderPipelineKey {
+            shader: self.shader.global_id(),
+            vertex_entry_point: self.vertex_entry_point.to_owned(),
+            fragment_entry_point: self.fragment_entry_point.map(str::to_owned),
+            vertex_buffers: self.vertex_buffers.clone(),
+            bind_group_layouts: self
+                .bind_group_layouts
+                .iter()
+                .map(|layout| layout.global_id())
+                .collect(),
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            color_targets: self.color_targets.clone(),
+            multisample: self.multisample,
+        }
+    }
+
+    pub(crate) fn build(self, device: &wgpu::Device) -> RenderPipeline {
+        let layout_label = format!("{} Layout", self.label);
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&bind_group_layout],
+            label: Some(&layout_label),
+            bind_group_layouts: &self.bind_group_layouts,
             push_constant_ranges: &[],
         });
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
+            label: Some(self.label),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[T::layout()],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: depth_texture_format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+                module: self.shader,
+                entry_point: self.vertex_entry_point,
+                buffers: &self.vertex_buffers,
             },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::Zero,
-                            dst_factor: wgpu::BlendFactor::One,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                    }),
-                    write_mask: wgpu::ColorWrites::all(),
-                })],
-            }),
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil,
+            multisample: self.multisample,
+            fragment: self
+                .fragment_entry_point
+                .map(|entry_point| wgpu::FragmentState {
+                    module: self.shader,
+                    entry_point,
+                    targets: &self.color_targets,
+                }),
             multiview: None,
         });
-        Self { render_pipeline }
+        RenderPipeline {
+            render_pipeline: Arc::new(render_pipeline),
+        }
     }
 }
@@ -0,0 +1,492 @@
+use std::sync::LazyLock;
+
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+
+use crate::{
+    error::Result,
+    resources::{Submesh, VertexAttribute},
+};
+
+use super::{
+    bind_group::{BindGroup, BindResource},
+    buffer::{DataBuffer, IndexBuffer, VertexBuffer},
+    light::{LightData, LightKind},
+    pipeline_cache::PipelineCache,
+    reflection::ShaderReflection,
+    render_pipeline::RenderPipeline,
+    texture::Texture,
+    SHADER_BINDINGS,
+};
+
+/// Bindings of `shadow.wgsl`.
+static BINDINGS: LazyLock<ShaderReflection> = LazyLock::new(|| {
+    ShaderReflection::from_wgsl("shadow.wgsl", include_str!("../shadow.wgsl"))
+        .expect("shadow.wgsl is valid WGSL")
+});
+
+/// Cascades of the main light, the first directional light.
+pub const CASCADE_COUNT: usize = 4;
+/// Layers of the shadow map array, other directional lights get one layer each.
+pub const MAX_SHADOW_LAYERS: usize = 8;
+/// Layers the shadow map array has at least. The GL backend guesses the view dimension
+/// from the layer count and would take a single layer array for a plain 2D texture.
+const MIN_SHADOW_LAYERS: u32 = 2;
+/// Shadow map sizes offered in the GUI, limited by the downlevel texture size.
+pub const SHADOW_RESOLUTIONS: [u32; 3] = [512, 1024, 2048];
+/// Blend between uniform (0) and logarithmic (1) cascade splits.
+const SPLIT_LAMBDA: f32 = 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of every shadow map layer in texels.
+    pub resolution: u32,
+    /// Subtracted from the depth of a fragment in light space.
+    pub depth_bias: f32,
+    /// Offset along the surface normal in world units before the lookup.
+    pub normal_bias: f32,
+    /// View distance covered by the shadow maps, the main light splits it into cascades.
+    pub max_distance: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            depth_bias: 0.001,
+            normal_bias: 0.02,
+            max_distance: 20.0,
+        }
+    }
+}
+
+/// Group 2 of `shader.wgsl`.
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct ShadowUniforms {
+    pub view_projections: [Mat4; MAX_SHADOW_LAYERS],
+    /// View depth at which each cascade of the main light ends.
+    pub cascade_splits: Vec4,
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+    /// Size of a texel in uv space, used for PCF.
+    pub texel_size: f32,
+    pub max_distance: f32,
+}
+
+/// Depth texture array rendered from the directional lights, and the pass filling it.
+pub struct ShadowMaps {
+    pub(crate) settings: ShadowSettings,
+    pub(crate) uniforms: DataBuffer<ShadowUniforms>,
+    /// Bound at group 2 of `shader.wgsl`.
+    pub(crate) bind_group: BindGroup,
+    texture: Texture,
+    layer_views: Vec<wgpu::TextureView>,
+    layer_count: u32,
+    /// One light view projection per layer, bound while rendering that layer.
+    pass_uniforms: Vec<(DataBuffer<Mat4>, BindGroup)>,
+    render_pipeline: RenderPipeline,
+    /// Bounding sphere of the shadow casters.
+    scene_bounds: (Vec3, f32),
+}
+
+impl ShadowMaps {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        settings: ShadowSettings,
+        scene_bounds: (Vec3, f32),
+        vertex_layout: wgpu::VertexBufferLayout<'static>,
+    ) -> Result<Self> {
+        let uniforms = DataBuffer::uniform(ShadowUniforms::default(), device);
+        let texture = Texture::shadow_map(device, settings.resolution, MIN_SHADOW_LAYERS);
+        let bind_group = Self::create_bind_group(device, pipeline_cache, &uniforms, &texture)?;
+        let pass_uniforms = (0..MAX_SHADOW_LAYERS)
+            .map(|_| {
+                let buffer = DataBuffer::uniform(Mat4::IDENTITY, device);
+                let pass_bind_group = BindGroup::new(
+                    device,
+                    pipeline_cache,
+                    &BINDINGS,
+                    0,
+                    &[BindResource::Buffer(&buffer.buffer)],
+                )?;
+                Ok((buffer, pass_bind_group))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let shader = pipeline_cache.shader(device, wgpu::include_wgsl!("../shadow.wgsl"));
+        // Depth only, without a fragment stage.
+        let render_pipeline = pipeline_cache.render_pipeline(
+            device,
+            RenderPipeline::builder("Shadow Pipeline", &shader)
+                .fragment_entry_point(None)
+                .vertex_buffer_layout(vertex_layout)
+                .bind_group_layouts(&[&pass_uniforms[0].1.bind_group_layout])
+                .depth(
+                    Texture::SHADOW_FORMAT,
+                    true,
+                    wgpu::CompareFunction::LessEqual,
+                )
+                // Keeps surfaces at grazing angles from shadowing themselves.
+                .depth_bias(wgpu::DepthBiasState {
+                    constant: 1,
+                    slope_scale: 1.5,
+                    clamp: 0.0,
+                }),
+        );
+        let layer_views = Self::create_layer_views(&texture);
+        Ok(Self {
+            settings,
+            uniforms,
+            bind_group,
+            texture,
+            layer_views,
+            layer_count: MIN_SHADOW_LAYERS,
+            pass_uniforms,
+            render_pipeline,
+            scene_bounds,
+        })
+    }
+
+    /// Fits the shadow maps of every light with a shadow layer to the camera and uploads
+    /// them, recreating the texture when the resolution or the number of layers changed.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_cache: &mut PipelineCache,
+        lights: &[LightData],
+        camera_view: Mat4,
+        camera_projection: Mat4,
+    ) -> Result<()> {
+        let layer_count = lights
+            .iter()
+            .filter_map(|light| {
+                Some(u32::try_from(light.shadow_layer).ok()? + light.shadow_cascades)
+            })
+            .max()
+            .unwrap_or(0)
+            .max(MIN_SHADOW_LAYERS);
+        if layer_count != self.layer_count
+            || self.settings.resolution != self.texture.texture.width()
+        {
+            self.texture = Texture::shadow_map(device, self.settings.resolution, layer_count);
+            self.layer_views = Self::create_layer_views(&self.texture);
+            self.bind_group =
+                Self::create_bind_group(device, pipeline_cache, &self.uniforms, &self.texture)?;
+            self.layer_count = layer_count;
+        }
+
+        let settings = self.settings;
+        let splits = cascade_splits(camera_projection, settings.max_distance);
+        let data = &mut self.uniforms.data;
+        for light in lights {
+            let Ok(first_layer) = usize::try_from(light.shadow_layer) else {
+                continue;
+            };
+            let ranges = if light.shadow_cascades as usize == CASCADE_COUNT {
+                splits.to_vec()
+            } else {
+                vec![(splits[0].0, settings.max_distance)]
+            };
+            for (i, (near, far)) in ranges.into_iter().enumerate() {
+                data.view_projections[first_layer + i] = fit_cascade(
+                    camera_view,
+                    camera_projection,
+                    near,
+                    far,
+                    light.direction,
+                    settings.resolution,
+                    self.scene_bounds,
+                );
+            }
+        }
+        data.cascade_splits = Vec4::from_array(splits.map(|(_, far)| far));
+        data.depth_bias = settings.depth_bias;
+        data.normal_bias = settings.normal_bias;
+        data.texel_size = 1.0 / settings.resolution as f32;
+        data.max_distance = settings.max_distance;
+        self.uniforms.update(queue);
+        for (layer, (buffer, _)) in self.pass_uniforms.iter_mut().enumerate() {
+            buffer.data = self.uniforms.data.view_projections[layer];
+            buffer.update(queue);
+        }
+        Ok(())
+    }
+
+    /// Renders the depth of `submeshes` into every layer.
+    pub(crate) fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        vertex_buffer: &VertexBuffer,
+        index_buffer: &IndexBuffer,
+        submeshes: &[Submesh],
+    ) {
+        for (view, (_, bind_group)) in self.layer_views.iter().zip(&self.pass_uniforms) {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            // Empty buffers cannot be bound, e.g. when the mesh failed to load.
+            if vertex_buffer.vertex_count == 0 || index_buffer.index_count == 0 {
+                continue;
+            }
+            render_pass.set_pipeline(&self.render_pipeline.render_pipeline);
+            render_pass.set_bind_group(0, &bind_group.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, vertex_buffer.buffer.slice(..));
+            render_pass.set_index_buffer(index_buffer.buffer.slice(..), index_buffer.format);
+            for submesh in submeshes {
+                render_pass.draw_indexed(submesh.indices.clone(), submesh.base_vertex, 0..1);
+            }
+        }
+    }
+
+    fn create_layer_views(texture: &Texture) -> Vec<wgpu::TextureView> {
+        (0..texture.texture.depth_or_array_layers())
+            .map(|layer| {
+                texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(&format!("Shadow Map Layer {layer}")),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        uniforms: &DataBuffer<ShadowUniforms>,
+        texture: &Texture,
+    ) -> Result<BindGroup> {
+        BindGroup::new(
+            device,
+            pipeline_cache,
+            &SHADER_BINDINGS,
+            2,
+            &[
+                BindResource::Buffer(&uniforms.buffer),
+                BindResource::Texture(texture),
+            ],
+        )
+    }
+}
+
+/// Gives the first directional light `CASCADE_COUNT` shadow layers and the other
+/// directional lights one each, while layers are left.
+pub fn assign_shadow_layers(lights: &mut [LightData]) {
+    let mut layer = 0;
+    for light in lights {
+        light.shadow_layer = -1;
+        light.shadow_cascades = 0;
+        if light.kind != LightKind::Directional as u32 {
+            continue;
+        }
+        let cascades = if layer == 0 { CASCADE_COUNT } else { 1 };
+        if layer + cascades > MAX_SHADOW_LAYERS {
+            break;
+        }
+        let (Ok(shadow_layer), Ok(shadow_cascades)) =
+            (i32::try_from(layer), u32::try_from(cascades))
+        else {
+            break;
+        };
+        light.shadow_layer = shadow_layer;
+        light.shadow_cascades = shadow_cascades;
+        layer += cascades;
+    }
+}
+
+/// Bounding sphere of `vertices`, centered on their bounding box.
+pub fn bounding_sphere(vertices: &[VertexAttribute]) -> (Vec3, f32) {
+    if vertices.is_empty() {
+        return (Vec3::ZERO, 0.0);
+    }
+    let (min, max) = vertices.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), vertex| (min.min(vertex.position), max.max(vertex.position)),
+    );
+    let center = (min + max) * 0.5;
+    let radius = vertices
+        .iter()
+        .map(|vertex| vertex.position.distance(center))
+        .fold(0.0, f32::max);
+    (center, radius)
+}
+
+/// View depth ranges of the cascades, between the camera near plane and `max_distance`.
+pub fn cascade_splits(camera_projection: Mat4, max_distance: f32) -> [(f32, f32); CASCADE_COUNT] {
+    // A point on the near plane of a left-handed perspective projection has depth 0.
+    let near = camera_projection
+        .inverse()
+        .project_point3(Vec3::new(0.0, 0.0, 0.0))
+        .z;
+    let far = max_distance.max(near * 2.0);
+    let split = |i: usize| {
+        let t = i as f32 / CASCADE_COUNT as f32;
+        let logarithmic = near * (far / near).powf(t);
+        let uniform = (far - near).mul_add(t, near);
+        SPLIT_LAMBDA.mul_add(logarithmic, (1.0 - SPLIT_LAMBDA) * uniform)
+    };
+    std::array::from_fn(|i| (split(i), split(i + 1)))
+}
+
+/// Orthographic view projection of a light shining along `-light_direction`, covering
+/// the part of the camera frustum between view depths `near` and `far`.
+///
+/// The cascade is fit to a bounding sphere and snapped to texels so shadow edges do
+/// not shimmer when the camera moves. The depth range reaches back to include every
+/// caster in `scene_bounds`.
+pub fn fit_cascade(
+    camera_view: Mat4,
+    camera_projection: Mat4,
+    near: f32,
+    far: f32,
+    light_direction: Vec3,
+    resolution: u32,
+    scene_bounds: (Vec3, f32),
+) -> Mat4 {
+    let inverse_projection = camera_projection.inverse();
+    let inverse_view = camera_view.inverse();
+    // Corners of the far plane in view space, scaled to the cascade depths.
+    let corners: Vec<Vec3> = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+        .into_iter()
+        .flat_map(|(x, y)| {
+            let far_corner = inverse_projection.project_point3(Vec3::new(x, y, 1.0));
+            [near, far]
+                .map(|depth| inverse_view.transform_point3(far_corner * (depth / far_corner.z)))
+        })
+        .collect();
+    let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0.0, f32::max);
+    // Rounded so the projection size does not change with the camera orientation.
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let direction = light_direction.normalize_or_zero();
+    let direction = if direction == Vec3::ZERO {
+        Vec3::Y
+    } else {
+        direction
+    };
+    let up = if direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+    let view = Mat4::look_to_lh(center, -direction, up);
+    let (scene_center, scene_radius) = scene_bounds;
+    let caster_distance = radius.max(scene_center.distance(center) + scene_radius);
+    let mut projection =
+        Mat4::orthographic_lh(-radius, radius, -radius, radius, -caster_distance, radius);
+
+    let half_resolution = resolution as f32 * 0.5;
+    let origin = (projection * view * Vec4::W).xy() * half_resolution;
+    let offset = (origin.round() - origin) / half_resolution;
+    projection.w_axis.x += offset.x;
+    projection.w_axis.y += offset.y;
+    projection * view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> (Mat4, Mat4) {
+        let view = Mat4::look_at_lh(Vec3::new(3.0, 2.0, -4.0), Vec3::ZERO, Vec3::Y);
+        let projection = Mat4::perspective_lh(45_f32.to_radians(), 1.5, 0.01, 100.0);
+        (view, projection)
+    }
+
+    #[test]
+    fn cascades_cover_the_shadow_distance() {
+        let (_, projection) = camera();
+        let splits = cascade_splits(projection, 20.0);
+
+        assert!((splits[0].0 - 0.01).abs() < 1e-4);
+        assert!((splits[CASCADE_COUNT - 1].1 - 20.0).abs() < 1e-3);
+        for pair in splits.windows(2) {
+            assert!(pair[0].0 < pair[0].1);
+            assert!((pair[0].1 - pair[1].0).abs() < f32::EPSILON);
+        }
+    }
+
+    #[test]
+    fn cascade_contains_its_frustum_slice() {
+        let (view, projection) = camera();
+        let light_direction = Vec3::new(0.5, 0.9, 0.1);
+        let scene_bounds = (Vec3::ZERO, 30.0);
+        let inverse_view_projection = (projection * view).inverse();
+        for (near, far) in cascade_splits(projection, 20.0) {
+            let light_view_projection = fit_cascade(
+                view,
+                projection,
+                near,
+                far,
+                light_direction,
+                1024,
+                scene_bounds,
+            );
+            // A point halfway through the slice, in the middle of the screen.
+            let depth = (near + far) * 0.5;
+            let far_center = inverse_view_projection.project_point3(Vec3::Z);
+            let camera_position = view.inverse().transform_point3(Vec3::ZERO);
+            let point = camera_position + (far_center - camera_position).normalize() * depth;
+
+            let clip = light_view_projection.project_point3(point);
+            assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0, "{clip}");
+            assert!((0.0..=1.0).contains(&clip.z), "{clip}");
+            // Casters between the light and the slice are in front of it.
+            let caster = light_view_projection.project_point3(point + light_direction.normalize());
+            assert!((0.0..clip.z).contains(&caster.z), "{caster}");
+        }
+    }
+
+    #[test]
+    fn main_light_gets_the_cascades() {
+        let light = |kind| LightData {
+            kind: kind as u32,
+            ..Default::default()
+        };
+        let mut lights = vec![
+            light(LightKind::Point),
+            light(LightKind::Directional),
+            light(LightKind::Spot),
+        ];
+        lights.extend([light(LightKind::Directional); 5]);
+        assign_shadow_layers(&mut lights);
+
+        let layers: Vec<_> = lights
+            .iter()
+            .map(|data| (data.shadow_layer, data.shadow_cascades))
+            .collect();
+        assert_eq!(
+            layers,
+            [
+                (-1, 0),
+                (0, 4),
+                (-1, 0),
+                (4, 1),
+                (5, 1),
+                (6, 1),
+                (7, 1),
+                // Out of layers
+                (-1, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::{Arc, LazyLock};
+
+use glam::{Mat3, Mat4};
+
+use crate::error::Result;
+
+use super::{
+    bind_group::{BindGroup, BindResource},
+    buffer::DataBuffer,
+    pipeline_cache::PipelineCache,
+    reflection::ShaderReflection,
+    render_pipeline::RenderPipeline,
+    texture::Texture,
+};
+
+/// Bindings of `skybox.wgsl`.
+static BINDINGS: LazyLock<ShaderReflection> = LazyLock::new(|| {
+    ShaderReflection::from_wgsl("skybox.wgsl", include_str!("../skybox.wgsl"))
+        .expect("skybox.wgsl is valid WGSL")
+});
+
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct SkyboxUniforms {
+    pub inverse_view_projection: Mat4,
+    /// Scale applied to the environment radiance.
+    pub intensity: f32,
+    pub _padding: [f32; 3],
+}
+
+/// Draws a cubemap behind the scene, after the opaque geometry.
+pub struct Skybox {
+    pub(crate) uniforms: DataBuffer<SkyboxUniforms>,
+    // Kept alive as long as the bind group using it.
+    #[allow(dead_code)]
+    cubemap: Arc<Texture>,
+    bind_group: BindGroup,
+    render_pipeline: RenderPipeline,
+}
+
+impl Skybox {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        cubemap: Arc<Texture>,
+        depth_format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        let uniforms = DataBuffer::uniform(
+            SkyboxUniforms {
+                intensity: 1.0,
+                ..Default::default()
+            },
+            device,
+        );
+        let bind_group = Self::create_bind_group(device, pipeline_cache, &uniforms, &cubemap)?;
+
+        let shader = pipeline_cache.shader(device, wgpu::include_wgsl!("../skybox.wgsl"));
+        let render_pipeline = pipeline_cache.render_pipeline(
+            device,
+            RenderPipeline::builder("Skybox Pipeline", &shader)
+                .bind_group_layouts(&[&bind_group.bind_group_layout])
+                // On the far plane, so it only covers pixels the geometry left at the
+                // cleared depth of 1.
+                .depth(depth_format, false, wgpu::CompareFunction::Equal)
+                .color_target(Texture::HDR_FORMAT, None),
+        );
+        Ok(Self {
+            uniforms,
+            cubemap,
+            bind_group,
+            render_pipeline,
+        })
+    }
+
+    /// Points the skybox at the camera described by `view` and `projection`.
+    pub(crate) fn set_camera(&mut self, view: Mat4, projection: Mat4) {
+        let rotation = Mat4::from_mat3(Mat3::from_mat4(view));
+        self.uniforms.data.inverse_view_projection = (projection * rotation).inverse();
+    }
+
+    pub(crate) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.render_pipeline.render_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group.bind_group, &[]);
+        // A single triangle covering the screen, generated in `vs_main`.
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        uniforms: &DataBuffer<SkyboxUniforms>,
+        cubemap: &Texture,
+    ) -> Result<BindGroup> {
+        BindGroup::new(
+            device,
+            pipeline_cache,
+            &BINDINGS,
+            0,
+            &[
+                BindResource::Buffer(&uniforms.buffer),
+                BindResource::Texture(cubemap),
+            ],
+        )
+    }
+}
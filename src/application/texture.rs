@@ -1,21 +1,161 @@
-use crate::{compute, resources::load_texture};
+use crate::{
+    compute,
+    error::{validation_scope, Result},
+    resources::{load_cross, load_cube_faces, load_texture, ColorSpace, TextureSource},
+};
 
-use std::path::Path;
+use image::RgbaImage;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use super::wgpu_context::WgpuContext;
+use super::{pipeline_cache::PipelineCache, wgpu_context::WgpuContext};
 
 pub struct Texture {
     pub(crate) texture: wgpu::Texture,
     pub(crate) view: wgpu::TextureView,
     pub(crate) sampler: wgpu::Sampler,
+    /// Dimension of `view`, checked against the shader when binding.
+    pub(crate) view_dimension: wgpu::TextureViewDimension,
 }
 
 impl Texture {
-    pub(crate) fn new(path: impl AsRef<Path>, wgpu: &WgpuContext) -> Self {
-        let (texture, view) = load_texture(path, &wgpu.device, &wgpu.queue).unwrap();
-        compute::generate_mipmaps(&texture, &wgpu.device, &wgpu.queue);
+    pub(crate) const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub(crate) const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-        let sampler = wgpu.device.create_sampler(&wgpu::SamplerDescriptor {
+    pub(crate) fn new(
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+        wgpu: &WgpuContext,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Result<Self> {
+        Self::from_source(
+            &TextureSource::File(path.as_ref().to_owned()),
+            color_space,
+            wgpu,
+            pipeline_cache,
+        )
+    }
+
+    pub(crate) fn from_source(
+        source: &TextureSource,
+        color_space: ColorSpace,
+        wgpu: &WgpuContext,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Result<Self> {
+        let (texture, view) = load_texture(source, color_space, &wgpu.device, &wgpu.queue)?;
+        compute::generate_mipmaps(&texture, pipeline_cache, &wgpu.device, &wgpu.queue)?;
+
+        let sampler = Self::create_sampler(&wgpu.device, texture.mip_level_count());
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        })
+    }
+
+    /// A 1x1 texture of a single color, used for material maps that are not set.
+    pub(crate) fn solid(
+        color: [u8; 4],
+        color_space: ColorSpace,
+        wgpu: &WgpuContext,
+    ) -> Result<Self> {
+        Self::from_pixels("Solid Texture", 1, 1, &color, color_space, wgpu)
+    }
+
+    /// A magenta and black checkerboard of `size`x`size` pixels with 8 pixel cells.
+    pub(crate) fn checkerboard(size: u32, wgpu: &WgpuContext) -> Result<Self> {
+        let pixels: Vec<u8> = (0..size * size)
+            .flat_map(|i| {
+                let (x, y) = (i % size, i / size);
+                if (x / 8 + y / 8) % 2 == 0 {
+                    [255, 0, 255, 255]
+                } else {
+                    [0, 0, 0, 255]
+                }
+            })
+            .collect();
+        Self::from_pixels(
+            "Checkerboard Texture",
+            size,
+            size,
+            &pixels,
+            ColorSpace::Srgb,
+            wgpu,
+        )
+    }
+
+    /// Creates a texture without mip levels from tightly packed RGBA pixels.
+    fn from_pixels(
+        label: &str,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        color_space: ColorSpace,
+        wgpu: &WgpuContext,
+    ) -> Result<Self> {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        validation_scope(&wgpu.device, || {
+            let texture = wgpu.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: color_space.texture_format(),
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+            wgpu.queue.write_texture(
+                texture.as_image_copy(),
+                pixels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                size,
+            );
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let sampler = Self::create_sampler(&wgpu.device, 1);
+            Self {
+                texture,
+                view,
+                sampler,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            }
+        })
+    }
+
+    /// Types `view` and `sampler` can be bound as, depth textures have comparison samplers.
+    pub(crate) fn binding_types(&self) -> [wgpu::BindingType; 2] {
+        let format = self.texture.format();
+        let sample_type = format
+            .sample_type(None, None)
+            .unwrap_or(wgpu::TextureSampleType::Float { filterable: false });
+        let sampler = if format.is_depth_stencil_format() {
+            wgpu::SamplerBindingType::Comparison
+        } else {
+            wgpu::SamplerBindingType::Filtering
+        };
+        [
+            wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: self.view_dimension,
+                multisampled: self.texture.sample_count() > 1,
+            },
+            wgpu::BindingType::Sampler(sampler),
+        ]
+    }
+
+    fn create_sampler(device: &wgpu::Device, mip_level_count: u32) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("Texture"),
             address_mode_u: wgpu::AddressMode::Repeat,
             address_mode_v: wgpu::AddressMode::Repeat,
@@ -24,17 +164,251 @@ impl Texture {
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             lod_min_clamp: 0.0,
-            lod_max_clamp: texture.mip_level_count() as f32,
+            lod_max_clamp: mip_level_count as f32,
             compare: None,
             anisotropy_clamp: 1,
             border_color: None,
+        })
+    }
+    /// Color target the scene is rendered to before tonemapping.
+    pub(crate) fn hdr_target(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("HDR Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("HDR Sampler"),
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        }
+    }
+
+    /// Empty HDR cubemap with a cube view and a trilinear sampler, faces are copied in.
+    pub(crate) fn cube(
+        device: &wgpu::Device,
+        label: &str,
+        size: u32,
+        mip_level_count: u32,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = Self::create_cube_view(&texture, label);
+        let sampler = Self::create_cube_sampler(device, label, mip_level_count);
+        Self {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::Cube,
+        }
+    }
+
+    /// Cubemap from six face images, in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub(crate) fn cube_from_faces(
+        paths: &[PathBuf; 6],
+        color_space: ColorSpace,
+        wgpu: &WgpuContext,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Result<Self> {
+        let faces = load_cube_faces(paths)?;
+        Self::from_cube_faces("Cubemap", &faces, color_space, wgpu, pipeline_cache)
+    }
+
+    /// Cubemap from a single image with the faces laid out as a horizontal or vertical cross.
+    pub(crate) fn cube_from_cross(
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+        wgpu: &WgpuContext,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Result<Self> {
+        let faces = load_cross(path)?;
+        Self::from_cube_faces("Cross Cubemap", &faces, color_space, wgpu, pipeline_cache)
+    }
+
+    /// Uploads the faces to a cubemap with a full mip chain.
+    ///
+    /// The mip chain of each face is generated in a 2D texture and copied in,
+    /// the GL backend cannot copy out of cubemaps.
+    fn from_cube_faces(
+        label: &str,
+        faces: &[RgbaImage; 6],
+        color_space: ColorSpace,
+        wgpu: &WgpuContext,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Result<Self> {
+        let size = faces[0].width();
+        let face_size = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        };
+        let mip_level_count = face_size.max_mips(wgpu::TextureDimension::D2);
+        let texture = wgpu.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                depth_or_array_layers: 6,
+                ..face_size
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: color_space.texture_format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (layer, face) in (0..).zip(faces) {
+            let face_texture = wgpu.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Cube Face"),
+                size: face_size,
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: color_space.texture_format(),
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            wgpu.queue.write_texture(
+                face_texture.as_image_copy(),
+                face.as_raw(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size),
+                    rows_per_image: Some(size),
+                },
+                face_size,
+            );
+            compute::generate_mipmaps(&face_texture, pipeline_cache, &wgpu.device, &wgpu.queue)?;
+
+            let mut encoder = wgpu
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            for level in 0..mip_level_count {
+                encoder.copy_texture_to_texture(
+                    wgpu::ImageCopyTexture {
+                        texture: &face_texture,
+                        mip_level: level,
+                        origin: wgpu::Origin3d::ZERO,
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::ImageCopyTexture {
+                        texture: &texture,
+                        mip_level: level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    face_size.mip_level_size(level, wgpu::TextureDimension::D2),
+                );
+            }
+            wgpu.queue.submit([encoder.finish()]);
+        }
+
+        let view = Self::create_cube_view(&texture, label);
+        let sampler = Self::create_cube_sampler(&wgpu.device, label, mip_level_count);
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::Cube,
+        })
+    }
+
+    fn create_cube_view(texture: &wgpu::Texture, label: &str) -> wgpu::TextureView {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..wgpu::TextureViewDescriptor::default()
+        })
+    }
+
+    /// Trilinear, the edges of the faces are stitched by the cube lookup.
+    fn create_cube_sampler(
+        device: &wgpu::Device,
+        label: &str,
+        mip_level_count: u32,
+    ) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_max_clamp: mip_level_count as f32,
+            ..wgpu::SamplerDescriptor::default()
+        })
+    }
+
+    /// Depth texture array with a comparison sampler, one layer per shadow map.
+    pub(crate) fn shadow_map(device: &wgpu::Device, resolution: u32, layers: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Map"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Map View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            // Linear filtering compares the four nearest texels.
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
         });
         Self {
             texture,
             view,
             sampler,
+            view_dimension: wgpu::TextureViewDimension::D2Array,
         }
     }
+
     pub(crate) fn depth(device: &wgpu::Device, width: u32, height: u32) -> Self {
         let depth_texture_format = wgpu::TextureFormat::Depth24Plus;
         let texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -78,6 +452,33 @@ impl Texture {
             texture,
             view,
             sampler,
+            view_dimension: wgpu::TextureViewDimension::D2,
         }
     }
 }
+
+/// Built-in textures standing in for material maps that are not set or fail to load.
+pub struct FallbackTextures {
+    /// Used for albedo maps that fail to load, hard to miss in a render.
+    pub(crate) checkerboard: Arc<Texture>,
+    /// Tangent space normal pointing straight out of the surface.
+    pub(crate) flat_normal: Arc<Texture>,
+    pub(crate) white: Arc<Texture>,
+    pub(crate) black: Arc<Texture>,
+}
+
+impl FallbackTextures {
+    pub(crate) fn new(wgpu: &WgpuContext) -> Result<Self> {
+        Ok(Self {
+            checkerboard: Arc::new(Texture::checkerboard(64, wgpu)?),
+            flat_normal: Arc::new(Texture::solid(
+                [128, 128, 255, 255],
+                ColorSpace::Linear,
+                wgpu,
+            )?),
+            // Black and white are the same in both color spaces.
+            white: Arc::new(Texture::solid([255; 4], ColorSpace::Linear, wgpu)?),
+            black: Arc::new(Texture::solid([0, 0, 0, 255], ColorSpace::Linear, wgpu)?),
+        })
+    }
+}
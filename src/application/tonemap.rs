@@ -0,0 +1,147 @@
+use std::{fmt::Display, sync::LazyLock};
+
+use crate::error::Result;
+
+use super::{
+    bind_group::{BindGroup, BindResource},
+    buffer::DataBuffer,
+    pipeline_cache::PipelineCache,
+    reflection::ShaderReflection,
+    render_pipeline::RenderPipeline,
+    texture::Texture,
+};
+
+/// Bindings of `tonemap.wgsl`.
+static BINDINGS: LazyLock<ShaderReflection> = LazyLock::new(|| {
+    ShaderReflection::from_wgsl("tonemap.wgsl", include_str!("../tonemap.wgsl"))
+        .expect("tonemap.wgsl is valid WGSL")
+});
+
+/// Operator mapping HDR scene colors into the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapping {
+    #[default]
+    Aces,
+    Reinhard,
+    Agx,
+}
+
+impl Tonemapping {
+    pub const ALL: [Self; 3] = [Self::Aces, Self::Reinhard, Self::Agx];
+}
+
+impl Display for Tonemapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Aces => "ACES",
+            Self::Reinhard => "Reinhard",
+            Self::Agx => "AgX",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct TonemapUniforms {
+    /// Linear scale applied to the scene color, `2^EV`.
+    pub exposure: f32,
+    /// Index of the `Tonemapping` variant.
+    pub tonemapping: u32,
+    /// 1 when the output target is not sRGB and `tonemap.wgsl` must encode itself.
+    pub encode_srgb: u32,
+    pub _padding: u32,
+}
+
+/// Fullscreen pass resolving the HDR scene target to the surface.
+pub struct TonemapPass {
+    pub(crate) uniforms: DataBuffer<TonemapUniforms>,
+    bind_group: BindGroup,
+    render_pipeline: RenderPipeline,
+}
+
+impl TonemapPass {
+    /// `exposure` is in stops.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        hdr_target: &Texture,
+        output_format: wgpu::TextureFormat,
+        exposure: f32,
+        tonemapping: Tonemapping,
+    ) -> Result<Self> {
+        let uniforms = DataBuffer::uniform(
+            TonemapUniforms {
+                exposure: exposure.exp2(),
+                tonemapping: tonemapping as u32,
+                encode_srgb: u32::from(!output_format.is_srgb()),
+                ..Default::default()
+            },
+            device,
+        );
+        let bind_group = Self::create_bind_group(device, pipeline_cache, &uniforms, hdr_target)?;
+
+        let shader = pipeline_cache.shader(device, wgpu::include_wgsl!("../tonemap.wgsl"));
+        let render_pipeline = pipeline_cache.render_pipeline(
+            device,
+            RenderPipeline::builder("Tonemap Pipeline", &shader)
+                .bind_group_layouts(&[&bind_group.bind_group_layout])
+                .color_target(output_format, None),
+        );
+        Ok(Self {
+            uniforms,
+            bind_group,
+            render_pipeline,
+        })
+    }
+
+    /// Binds a new HDR target, e.g. after a resize.
+    pub(crate) fn set_input(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        hdr_target: &Texture,
+    ) -> Result<()> {
+        self.bind_group =
+            Self::create_bind_group(device, pipeline_cache, &self.uniforms, hdr_target)?;
+        Ok(())
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        uniforms: &DataBuffer<TonemapUniforms>,
+        hdr_target: &Texture,
+    ) -> Result<BindGroup> {
+        BindGroup::new(
+            device,
+            pipeline_cache,
+            &BINDINGS,
+            0,
+            &[
+                BindResource::Buffer(&uniforms.buffer),
+                BindResource::Texture(hdr_target),
+            ],
+        )
+    }
+
+    pub(crate) fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.render_pipeline.render_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group.bind_group, &[]);
+        // A single triangle covering the screen, generated in `vs_main`.
+        render_pass.draw(0..3, 0..1);
+    }
+}
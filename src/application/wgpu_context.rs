@@ -4,19 +4,43 @@ use winit::window::Window;
 
 use std::sync::Arc;
 
+use super::texture::Texture;
+
 pub struct WgpuContext {
-    pub(crate) surface: wgpu::Surface<'static>,
+    pub(crate) surface: Option<wgpu::Surface<'static>>,
     pub(crate) device: wgpu::Device,
     pub(crate) queue: wgpu::Queue,
     pub(crate) config: wgpu::SurfaceConfiguration,
+    /// Color target used instead of the swapchain when there is no surface.
+    pub(crate) offscreen: Option<wgpu::Texture>,
+    /// Depth target of the frames, resized with them.
+    pub(crate) depth_texture: Texture,
+}
+
+/// The color target of a single frame, either a swapchain image or the offscreen texture.
+pub struct Frame {
+    surface_texture: Option<wgpu::SurfaceTexture>,
+    pub(crate) view: wgpu::TextureView,
+}
+
+impl Frame {
+    pub(crate) fn present(self) {
+        if let Some(surface_texture) = self.surface_texture {
+            surface_texture.present();
+        }
+    }
 }
 
 impl WgpuContext {
-    pub(crate) fn get_current_texture(&mut self) -> wgpu::SurfaceTexture {
-        match self.surface.get_current_texture() {
+    fn get_current_texture(
+        surface: &wgpu::Surface<'static>,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> wgpu::SurfaceTexture {
+        match surface.get_current_texture() {
             Ok(frame) => frame,
             // If we timed out, just try again
-            Err(wgpu::SurfaceError::Timeout) => self.surface
+            Err(wgpu::SurfaceError::Timeout) => surface
                 .get_current_texture()
                 .expect("Failed to acquire next surface texture!"),
             Err(
@@ -26,13 +50,37 @@ impl WgpuContext {
                 // If OutOfMemory happens, reconfiguring may not help, but we might as well try
                 | wgpu::SurfaceError::OutOfMemory,
             ) => {
-                self.surface.configure(&self.device, &self.config);
-                self.surface
+                surface.configure(device, config);
+                surface
                     .get_current_texture()
                     .expect("Failed to acquire next surface texture!")
             }
         }
     }
+
+    pub(crate) fn current_frame(&self) -> Frame {
+        match (&self.surface, &self.offscreen) {
+            (Some(surface), _) => {
+                let surface_texture =
+                    Self::get_current_texture(surface, &self.device, &self.config);
+                let view = surface_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                Frame {
+                    surface_texture: Some(surface_texture),
+                    view,
+                }
+            }
+            (None, Some(texture)) => Frame {
+                surface_texture: None,
+                view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            },
+            (None, None) => {
+                unreachable!("WgpuContext has neither a surface nor an offscreen target")
+            }
+        }
+    }
+
     pub(crate) fn new(window: &Arc<Window>) -> Self {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
         info!("{instance:?}");
@@ -48,31 +96,12 @@ impl WgpuContext {
             .unwrap();
         info!("{adapter:?}");
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device"),
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::downlevel_defaults(),
-                },
-                None,
-            )
-            .block_on()
-            .unwrap();
-
-        device.on_uncaptured_error(Box::new(|e| {
-            error!("{e}");
-        }));
-
-        queue.on_submitted_work_done(Box::new(|| {
-            info!("Submitted work on queue done.");
-        }));
+        let (device, queue) = Self::request_device(&adapter);
 
         let surface_caps = surface.get_capabilities(&adapter);
         info!("{surface_caps:?}");
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result in all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
+        // Prefer an sRGB surface so blending happens in linear space. On other surfaces
+        // `tonemap.wgsl` encodes its output itself.
         let surface_format = surface_caps
             .formats
             .iter()
@@ -91,18 +120,126 @@ impl WgpuContext {
         };
         info!("{config:?}");
         surface.configure(&device, &config);
+        let depth_texture = Texture::depth(&device, config.width, config.height);
 
         Self {
-            surface,
+            surface: Some(surface),
             device,
             queue,
             config,
+            offscreen: None,
+            depth_texture,
         }
     }
 
+    /// Creates a context without a window, rendering into owned `width`x`height` color
+    /// and depth textures.
+    ///
+    /// Any adapter is accepted, including the fallback (software) adapter, so this
+    /// works on machines without a GPU. Returns `None` if no adapter is available.
+    pub(crate) fn headless(width: u32, height: u32) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all),
+            ..Default::default()
+        });
+        info!("{instance:?}");
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                compatible_surface: None,
+                ..Default::default()
+            })
+            .block_on()
+            .or_else(|| {
+                instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        compatible_surface: None,
+                        force_fallback_adapter: true,
+                        ..Default::default()
+                    })
+                    .block_on()
+            })?;
+        info!("{adapter:?}");
+
+        let (device, queue) = Self::request_device(&adapter);
+
+        // The offscreen target mirrors the sRGB swapchain format preferred by `new`.
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        info!("{config:?}");
+        let offscreen = Self::create_offscreen_texture(&device, &config);
+        let depth_texture = Texture::depth(&device, width, height);
+
+        Some(Self {
+            surface: None,
+            device,
+            queue,
+            config,
+            offscreen: Some(offscreen),
+            depth_texture,
+        })
+    }
+
+    fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Device"),
+                    required_features: wgpu::Features::empty(),
+                    required_limits: wgpu::Limits::downlevel_defaults(),
+                },
+                None,
+            )
+            .block_on()
+            .unwrap();
+
+        device.on_uncaptured_error(Box::new(|e| {
+            error!("{e}");
+        }));
+
+        queue.on_submitted_work_done(Box::new(|| {
+            info!("Submitted work on queue done.");
+        }));
+        (device, queue)
+    }
+
+    fn create_offscreen_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Texture"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+            view_formats: &[],
+        })
+    }
+
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
         self.config.width = width;
         self.config.height = height;
-        self.surface.configure(&self.device, &self.config);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+        if self.offscreen.is_some() {
+            self.offscreen = Some(Self::create_offscreen_texture(&self.device, &self.config));
+        }
+        self.depth_texture = Texture::depth(&self.device, width, height);
     }
 }
@@ -0,0 +1,177 @@
+// Precomputes the image based lighting of an environment.
+//
+// Cubemaps cannot be storage textures everywhere, so cube passes write the six
+// faces stacked vertically in a 2D texture, +X, -X, +Y, -Y, +Z then -Z, which
+// are then copied into the cubemap faces.
+
+struct Parameters {
+    face_size: u32,
+    sample_count: u32,
+    // Of the prefiltered mip level being written
+    roughness: f32,
+}
+
+@group(0) @binding(0) var<uniform> parameters: Parameters;
+@group(0) @binding(1) var output: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(0) var equirectangular: texture_2d<f32>;
+
+@group(1) @binding(1) var environment: texture_cube<f32>;
+@group(1) @binding(2) var environment_sampler: sampler;
+
+const PI: f32 = 3.14159265;
+
+// Direction through the center of texel `id` of the stacked faces, the inverse
+// of the cube face selection of WebGPU
+fn cube_direction(id: vec2u) -> vec3f {
+    let size = parameters.face_size;
+    let face = id.y / size;
+    let uv = (vec2f(f32(id.x), f32(id.y % size)) + 0.5) / f32(size) * 2.0 - 1.0;
+    var direction: vec3f;
+    switch face {
+        case 0u: { direction = vec3f(1.0, -uv.y, -uv.x); }
+        case 1u: { direction = vec3f(-1.0, -uv.y, uv.x); }
+        case 2u: { direction = vec3f(uv.x, 1.0, uv.y); }
+        case 3u: { direction = vec3f(uv.x, -1.0, -uv.y); }
+        case 4u: { direction = vec3f(uv.x, -uv.y, 1.0); }
+        default: { direction = vec3f(-uv.x, -uv.y, -1.0); }
+    }
+    return normalize(direction);
+}
+
+fn in_bounds(id: vec3u) -> bool {
+    return id.x < parameters.face_size && id.y < parameters.face_size * 6u;
+}
+
+fn load_equirectangular(texel: vec2i, size: vec2i) -> vec3f {
+    // Wraps around horizontally, clamps at the poles
+    let x = (texel.x + size.x) % size.x;
+    let y = clamp(texel.y, 0, size.y - 1);
+    return textureLoad(equirectangular, vec2i(x, y), 0).rgb;
+}
+
+// Bilinear sample, float textures are not filterable everywhere
+fn sample_equirectangular(direction: vec3f) -> vec3f {
+    let size = vec2i(textureDimensions(equirectangular));
+    // The middle of the image faces +Z, +X is to its right in the left-handed world
+    let uv = vec2f(
+        atan2(direction.x, direction.z) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    let texel = uv * vec2f(size) - 0.5;
+    let base = vec2i(floor(texel));
+    let t = fract(texel);
+    let top = mix(load_equirectangular(base, size), load_equirectangular(base + vec2i(1, 0), size), t.x);
+    let bottom = mix(load_equirectangular(base + vec2i(0, 1), size), load_equirectangular(base + vec2i(1, 1), size), t.x);
+    return mix(top, bottom, t.y);
+}
+
+fn hammersley(i: u32, count: u32) -> vec2f {
+    return vec2f(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Rotates tangent space vectors around `normal` to world space
+fn tangent_to_world(normal: vec3f) -> mat3x3f {
+    let up = select(vec3f(1.0, 0.0, 0.0), vec3f(0.0, 1.0, 0.0), abs(normal.y) < 0.999);
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return mat3x3f(tangent, bitangent, normal);
+}
+
+// Half vector distributed like the GGX lobe around +Z
+fn importance_sample_ggx(xi: vec2f, alpha: f32) -> vec3f {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3f(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+@compute @workgroup_size(8, 8)
+fn equirectangular_to_cube(@builtin(global_invocation_id) id: vec3u) {
+    if !in_bounds(id) {
+        return;
+    }
+    textureStore(output, id.xy, vec4f(sample_equirectangular(cube_direction(id.xy)), 1.0));
+}
+
+// Cosine weighted average of the radiance over the hemisphere, irradiance / PI
+@compute @workgroup_size(8, 8)
+fn irradiance(@builtin(global_invocation_id) id: vec3u) {
+    if !in_bounds(id) {
+        return;
+    }
+    let normal = cube_direction(id.xy);
+    let to_world = tangent_to_world(normal);
+    var sum = vec3f(0.0);
+    for (var i = 0u; i < parameters.sample_count; i++) {
+        let xi = hammersley(i, parameters.sample_count);
+        let phi = 2.0 * PI * xi.x;
+        let sin_theta = sqrt(xi.y);
+        let direction = vec3f(cos(phi) * sin_theta, sin(phi) * sin_theta, sqrt(1.0 - xi.y));
+        sum += textureSampleLevel(environment, environment_sampler, to_world * direction, 0.0).rgb;
+    }
+    textureStore(output, id.xy, vec4f(sum / f32(parameters.sample_count), 1.0));
+}
+
+// Radiance convolved with the GGX lobe of `parameters.roughness`, assuming the
+// view direction is the reflection direction
+@compute @workgroup_size(8, 8)
+fn prefilter(@builtin(global_invocation_id) id: vec3u) {
+    if !in_bounds(id) {
+        return;
+    }
+    let normal = cube_direction(id.xy);
+    if parameters.roughness == 0.0 {
+        let radiance = textureSampleLevel(environment, environment_sampler, normal, 0.0).rgb;
+        textureStore(output, id.xy, vec4f(radiance, 1.0));
+        return;
+    }
+    let to_world = tangent_to_world(normal);
+    let alpha = parameters.roughness * parameters.roughness;
+    var sum = vec3f(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < parameters.sample_count; i++) {
+        let half_vector = to_world * importance_sample_ggx(hammersley(i, parameters.sample_count), alpha);
+        let direction = reflect(-normal, half_vector);
+        let NoL = dot(normal, direction);
+        if NoL > 0.0 {
+            sum += textureSampleLevel(environment, environment_sampler, direction, 0.0).rgb * NoL;
+            weight += NoL;
+        }
+    }
+    textureStore(output, id.xy, vec4f(sum / max(weight, 0.0001), 1.0));
+}
+
+// Scale and bias applied to F0 by the split sum approximation, indexed by NoV
+// horizontally and roughness vertically
+@compute @workgroup_size(8, 8)
+fn brdf_lut(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(output);
+    if any(id.xy >= size) {
+        return;
+    }
+    let NoV = (f32(id.x) + 0.5) / f32(size.x);
+    let roughness = (f32(id.y) + 0.5) / f32(size.y);
+    let alpha = roughness * roughness;
+    let V = vec3f(sqrt(1.0 - NoV * NoV), 0.0, NoV);
+    // Schlick-GGX with the k of image based lighting
+    let k = alpha / 2.0;
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < parameters.sample_count; i++) {
+        let H = importance_sample_ggx(hammersley(i, parameters.sample_count), alpha);
+        let L = reflect(-V, H);
+        let NoL = L.z;
+        if NoL > 0.0 {
+            let NoH = max(H.z, 0.0);
+            let VoH = max(dot(V, H), 0.0);
+            let G = (NoV / (NoV * (1.0 - k) + k)) * (NoL / (NoL * (1.0 - k) + k));
+            let G_visibility = G * VoH / max(NoH * NoV, 0.0001);
+            let Fc = pow(1.0 - VoH, 5.0);
+            scale += (1.0 - Fc) * G_visibility;
+            bias += Fc * G_visibility;
+        }
+    }
+    let count = f32(parameters.sample_count);
+    textureStore(output, id.xy, vec4f(scale / count, bias / count, 0.0, 1.0));
+}
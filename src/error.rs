@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use pollster::FutureExt;
+
+use crate::resources::PointsError;
+
+/// Everything that can go wrong while loading assets or running GPU work.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to load OBJ {}: {source}", path.display())]
+    Obj {
+        path: PathBuf,
+        source: tobj::LoadError,
+    },
+    #[error("failed to load glTF {}: {source}", path.display())]
+    Gltf { path: PathBuf, source: gltf::Error },
+    #[error("failed to load {}: {source}", path.display())]
+    Points { path: PathBuf, source: PointsError },
+    #[error("image {name}: {source}")]
+    Image {
+        name: String,
+        source: image::ImageError,
+    },
+    #[error("GPU readback failed: {0}")]
+    Readback(String),
+    #[error("GPU validation failed: {0}")]
+    Gpu(#[from] wgpu::Error),
+    /// WGSL that naga rejects, or resources that do not match its bindings.
+    #[error("shader {name}: {message}")]
+    Shader { name: String, message: String },
+    /// Asset content that parsed but cannot be used.
+    #[error("invalid asset: {0}")]
+    Validation(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Runs `f` and returns the first validation error it raised on `device`.
+///
+/// Without a scope, validation errors only reach the uncaptured error handler.
+pub fn validation_scope<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = f();
+    device
+        .pop_error_scope()
+        .block_on()
+        .map_or(Ok(value), |error| Err(error.into()))
+}
@@ -0,0 +1,672 @@
+//! Golden-image regression tests for the render and compute shaders.
+//!
+//! Every test renders offscreen through `WgpuContext::headless`, so a software
+//! adapter (e.g. llvmpipe) is enough. When no adapter exists at all the tests
+//! fail, unless `SKIP_GPU_TESTS` is set. Run with `UPDATE_GOLDEN=1` to rewrite
+//! the reference images in `resources/golden` after an intentional change.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use glam::{Mat3, Vec3};
+use image::{Rgba, RgbaImage};
+use tracing::warn;
+
+use crate::{
+    application::{
+        bind_group::{BindGroup, BindResource},
+        buffer::DataBuffer,
+        environment::{Environment, DEFAULT_AMBIENT},
+        light::{Light, LightKind},
+        material::ShadingModel,
+        pipeline_cache::{PipelineCache, PipelineCacheStats},
+        reflection::ShaderReflection,
+        render_pipeline::RenderPipeline,
+        shadow::ShadowSettings,
+        texture::Texture,
+        tonemap::{TonemapUniforms, Tonemapping},
+        wgpu_context::WgpuContext,
+        ApplicationState, Camera, ComputeUniforms, LightUniforms, SceneDescriptor,
+    },
+    compute,
+    error::{validation_scope, Error},
+    resources::{read_texture, write_mipmaps, ColorSpace, CubemapSource, VertexEncoding},
+};
+
+const GOLDEN_DIR: &str = "resources/golden";
+/// Largest per-channel difference allowed between a pixel and its reference.
+const TOLERANCE: u8 = 2;
+
+/// Serializes GPU access, some drivers do not like several devices used at once.
+static GPU: Mutex<()> = Mutex::new(());
+
+fn lock_gpu() -> MutexGuard<'static, ()> {
+    let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+    GPU.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn output_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join("wgpu-cpp-golden");
+    std::fs::create_dir_all(&dir).expect("Failed to create golden output directory");
+    dir
+}
+
+/// A small deterministic RGBA pattern with gradients and hard edges.
+fn write_test_pattern() -> PathBuf {
+    let path = output_dir().join("pattern.png");
+    RgbaImage::from_fn(64, 64, |x, y| {
+        let checker = if (x / 8 + y / 8) % 2 == 0 { 255 } else { 0 };
+        Rgba([(x * 4) as u8, (y * 4) as u8, checker, 255])
+    })
+    .save(&path)
+    .expect("Failed to write test pattern");
+    path
+}
+
+/// `resources/cube.obj` saved as `<name>.obj` with a material using `albedo`.
+fn write_textured_cube(name: &str, albedo: &str) -> PathBuf {
+    write_test_pattern();
+    std::fs::write(
+        output_dir().join(format!("{name}.mtl")),
+        format!("newmtl Pattern\nKd 1.0 1.0 1.0\nmap_Kd {albedo}\n"),
+    )
+    .expect("Failed to write cube material");
+    let cube = std::fs::read_to_string("resources/cube.obj").expect("Failed to read cube");
+    let path = output_dir().join(format!("{name}.obj"));
+    std::fs::write(&path, format!("mtllib {name}.mtl\nusemtl Pattern\n{cube}"))
+        .expect("Failed to write cube");
+    path
+}
+
+/// Compares `actual` against `resources/golden/<name>.png`.
+///
+/// On failure the rendered image and a diff image (mismatching pixels in red
+/// over the darkened reference) are written next to the test pattern.
+fn assert_golden(name: &str, actual: &RgbaImage) {
+    let reference_path = Path::new(GOLDEN_DIR).join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(GOLDEN_DIR).expect("Failed to create golden directory");
+        actual
+            .save(&reference_path)
+            .expect("Failed to write golden image");
+        return;
+    }
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "Missing golden image {}: {e}, run with UPDATE_GOLDEN=1",
+                reference_path.display()
+            )
+        })
+        .into_rgba8();
+    assert_eq!(
+        actual.dimensions(),
+        reference.dimensions(),
+        "{name}: size differs from the golden image"
+    );
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatches = 0;
+    for ((a, r), d) in actual
+        .pixels()
+        .zip(reference.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let difference = a.0.iter().zip(r.0).map(|(a, r)| a.abs_diff(r)).max();
+        *d = if difference.unwrap_or(0) > TOLERANCE {
+            mismatches += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([r[0] / 4, r[1] / 4, r[2] / 4, 255])
+        };
+    }
+    if mismatches > 0 {
+        let actual_path = output_dir().join(format!("{name}_actual.png"));
+        let diff_path = output_dir().join(format!("{name}_diff.png"));
+        actual.save(&actual_path).expect("Failed to write image");
+        diff.save(&diff_path).expect("Failed to write diff image");
+        panic!(
+            "{name}: {mismatches} pixels differ from {} by more than {TOLERANCE}, see {} and {}",
+            reference_path.display(),
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn test_scene() -> SceneDescriptor {
+    SceneDescriptor {
+        mesh: write_textured_cube("cube", "pattern.png"),
+        lights: vec![
+            Light::directional(Vec3::new(0.5, -0.9, 0.1), Vec3::new(1.0, 0.9, 0.6)),
+            Light::directional(Vec3::new(0.2, 0.4, 0.3), Vec3::new(0.6, 0.9, 1.0)),
+        ],
+        lighting: LightUniforms {
+            hardness: 16.0,
+            diffuse: 1.0,
+            specular: 0.5,
+            ..Default::default()
+        },
+        camera: Camera {
+            orbit_radius: 5.0,
+            yaw: 0.6,
+            pitch: 0.4,
+        },
+        environment: None,
+        ..Default::default()
+    }
+}
+
+/// Fails the calling test, or only warns when `SKIP_GPU_TESTS` is set, so machines
+/// without an adapter do not pass the GPU tests silently.
+fn no_adapter() {
+    assert!(
+        std::env::var_os("SKIP_GPU_TESTS").is_some(),
+        "No adapter available, set SKIP_GPU_TESTS=1 to skip the GPU tests"
+    );
+    warn!("No adapter available, skipping GPU test");
+}
+
+fn headless_context() -> Option<WgpuContext> {
+    let context = WgpuContext::headless(1, 1);
+    if context.is_none() {
+        no_adapter();
+    }
+    context
+}
+
+/// Renders `scene` offscreen at 128x128 and compares it against the golden image `name`.
+fn render_golden(name: &str, scene: &SceneDescriptor) {
+    let Some(mut state) =
+        ApplicationState::headless(128, 128, scene).expect("Failed to load the scene")
+    else {
+        no_adapter();
+        return;
+    };
+    state.render();
+    let frame = state.read_frame().expect("Failed to read back frame");
+    assert_golden(name, &frame);
+}
+
+#[test]
+fn render_shader() {
+    let _gpu = lock_gpu();
+    let scene = test_scene();
+    render_golden("shader", &scene);
+}
+
+#[test]
+fn packed_vertices_match_full_vertices() {
+    let _gpu = lock_gpu();
+    let mut scene = test_scene();
+    scene.load_options.vertex_encoding = VertexEncoding::Packed;
+    // Quantizing the vertices changes the cube by less than the tolerance.
+    render_golden("shader", &scene);
+}
+
+#[test]
+fn optimized_mesh_renders_the_same() {
+    let _gpu = lock_gpu();
+    let mut scene = test_scene();
+    scene.load_options.optimize = true;
+    render_golden("shader", &scene);
+}
+
+#[test]
+fn missing_texture_fallback() {
+    let _gpu = lock_gpu();
+    let scene = SceneDescriptor {
+        mesh: write_textured_cube("cube_missing_texture", "missing.png"),
+        ..test_scene()
+    };
+    render_golden("missing_texture_fallback", &scene);
+}
+
+#[test]
+fn tonemapping_operators() {
+    let _gpu = lock_gpu();
+    for tonemapping in Tonemapping::ALL {
+        // Lights well above 1.0 would clip without tonemapping.
+        let mut scene = SceneDescriptor {
+            exposure: -1.0,
+            tonemapping,
+            ..test_scene()
+        };
+        for light in &mut scene.lights {
+            light.intensity = 4.0;
+        }
+        render_golden(
+            &format!("tonemap_{}", tonemapping.to_string().to_lowercase()),
+            &scene,
+        );
+    }
+}
+
+#[test]
+fn point_and_spot_lights() {
+    let _gpu = lock_gpu();
+    let scene = SceneDescriptor {
+        lights: vec![
+            Light {
+                kind: LightKind::Point,
+                color: Vec3::new(1.0, 0.3, 0.1),
+                intensity: 4.0,
+                position: Vec3::new(2.0, 1.5, 0.0),
+                range: 4.0,
+                ..Default::default()
+            },
+            Light {
+                kind: LightKind::Spot,
+                color: Vec3::new(0.2, 0.5, 1.0),
+                intensity: 8.0,
+                position: Vec3::new(0.0, -3.0, 0.0),
+                direction: Vec3::NEG_Y,
+                inner_angle: 10_f32.to_radians(),
+                outer_angle: 20_f32.to_radians(),
+                ..Default::default()
+            },
+        ],
+        ..test_scene()
+    };
+    render_golden("point_and_spot_lights", &scene);
+}
+
+#[test]
+fn directional_shadows() {
+    let _gpu = lock_gpu();
+    // The textured cube floating above a ground quad, using relative OBJ indices.
+    let path = write_textured_cube("shadow_scene", "pattern.png");
+    let cube = std::fs::read_to_string(&path).expect("Failed to read cube");
+    let ground = "o Ground\n\
+        v -6 -2 -6\nv 6 -2 -6\nv 6 -2 6\nv -6 -2 6\n\
+        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 1 0\n\
+        f -4/-4/-1 -3/-3/-1 -2/-2/-1\nf -4/-4/-1 -2/-2/-1 -1/-1/-1\n";
+    std::fs::write(&path, format!("{cube}{ground}")).expect("Failed to write shadow scene");
+    let scene = SceneDescriptor {
+        mesh: path,
+        lights: vec![Light::directional(Vec3::new(0.4, 1.0, 0.3), Vec3::ONE)],
+        camera: Camera {
+            orbit_radius: 9.0,
+            yaw: 0.6,
+            pitch: -0.6,
+        },
+        shadows: ShadowSettings {
+            resolution: 512,
+            ..Default::default()
+        },
+        ..test_scene()
+    };
+    render_golden("directional_shadows", &scene);
+}
+
+#[test]
+fn pbr_materials() {
+    let _gpu = lock_gpu();
+    for (name, metallic, roughness) in [("pbr_dielectric", 0.0, 0.5), ("pbr_metallic", 1.0, 0.3)] {
+        let mesh = write_textured_cube(name, "pattern.png");
+        std::fs::write(
+            output_dir().join(format!("{name}.mtl")),
+            format!(
+                "newmtl Pattern\nKd 1.0 0.8 0.6\nPm {metallic}\nPr {roughness}\nmap_Kd pattern.png\n"
+            ),
+        )
+        .expect("Failed to write cube material");
+        // The camera mirrored on the +Z face, which puts a highlight in its middle.
+        let mut scene = SceneDescriptor {
+            mesh,
+            shading_model: ShadingModel::Pbr,
+            ..test_scene()
+        };
+        scene.lights.push(Light {
+            kind: LightKind::Point,
+            intensity: 30.0,
+            position: Vec3::new(-2.6, 1.9, 3.8),
+            ..Default::default()
+        });
+        render_golden(name, &scene);
+    }
+}
+
+#[test]
+fn image_based_lighting() {
+    let _gpu = lock_gpu();
+    let mesh = write_textured_cube("image_based_lighting", "pattern.png");
+    std::fs::write(
+        output_dir().join("image_based_lighting.mtl"),
+        "newmtl Pattern\nKd 1.0 0.8 0.6\nPm 1.0\nPr 0.2\n",
+    )
+    .expect("Failed to write cube material");
+    // Only the environment lights the cube, which is drawn over the skybox.
+    let scene = SceneDescriptor {
+        mesh,
+        lights: Vec::new(),
+        shading_model: ShadingModel::Pbr,
+        environment: Some("resources/sky.hdr".into()),
+        ..test_scene()
+    };
+    render_golden("image_based_lighting", &scene);
+}
+
+#[test]
+fn cross_skybox() {
+    let _gpu = lock_gpu();
+    // Faces of distinct colors with a gradient towards +Y, so seams and
+    // orientation show up in the reference.
+    let colors = [
+        [255, 64, 64],
+        [64, 255, 255],
+        [64, 255, 64],
+        [255, 64, 255],
+        [64, 64, 255],
+        [255, 255, 64],
+    ];
+    let positions = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+    let size = 16;
+    let path = output_dir().join("cross.png");
+    RgbaImage::from_fn(4 * size, 3 * size, |x, y| {
+        let face = positions.iter().position(|&p| p == (x / size, y / size));
+        face.map_or(Rgba([0, 0, 0, 255]), |face| {
+            let shade = 255 - (y % size) * 8;
+            let [r, g, b] = colors[face].map(|c| (c * shade / 255) as u8);
+            Rgba([r, g, b, 255])
+        })
+    })
+    .save(&path)
+    .expect("Failed to write cross cubemap");
+    // The cube is drawn first, the skybox only fills the rest.
+    let scene = SceneDescriptor {
+        skybox: Some(CubemapSource::Cross(path)),
+        ..test_scene()
+    };
+    render_golden("cross_skybox", &scene);
+}
+
+#[test]
+fn pipeline_builder_states() {
+    let _gpu = lock_gpu();
+    let Some(wgpu) = headless_context() else {
+        return;
+    };
+    let shader = wgpu
+        .device
+        .create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Pipeline States"),
+            source: wgpu::ShaderSource::Wgsl(
+                "
+                @vertex
+                fn vs_points(@builtin(vertex_index) index: u32) -> @builtin(position) vec4f {
+                    return vec4f(f32(index), 0.0, 0.0, 1.0);
+                }
+
+                struct Targets {
+                    @location(0) color: vec4f,
+                    @location(1) glow: vec4f,
+                }
+
+                @fragment
+                fn fs_targets() -> Targets {
+                    return Targets(vec4f(1.0, 0.0, 0.0, 0.5), vec4f(0.0, 1.0, 0.0, 1.0));
+                }
+                "
+                .into(),
+            ),
+        });
+    let result = validation_scope(&wgpu.device, || {
+        RenderPipeline::builder("Lines", &shader)
+            .vertex_entry_point("vs_points")
+            .fragment_entry_point(Some("fs_targets"))
+            .topology(wgpu::PrimitiveTopology::LineStrip)
+            .color_target(Texture::HDR_FORMAT, None)
+            .color_target(wgpu::TextureFormat::Rgba8Unorm, None)
+            .build(&wgpu.device);
+        RenderPipeline::builder("Transparent", &shader)
+            .vertex_entry_point("vs_points")
+            .fragment_entry_point(Some("fs_targets"))
+            .cull_mode(Some(wgpu::Face::Back))
+            .front_face(wgpu::FrontFace::Cw)
+            .depth(
+                wgpu::TextureFormat::Depth24PlusStencil8,
+                false,
+                wgpu::CompareFunction::Less,
+            )
+            .stencil(wgpu::StencilState {
+                front: wgpu::StencilFaceState {
+                    compare: wgpu::CompareFunction::Equal,
+                    ..Default::default()
+                },
+                read_mask: 0xff,
+                ..Default::default()
+            })
+            .sample_count(4)
+            .color_target(Texture::HDR_FORMAT, Some(wgpu::BlendState::ALPHA_BLENDING))
+            .color_target(
+                wgpu::TextureFormat::Rgba8Unorm,
+                Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            )
+            .build(&wgpu.device);
+    });
+    assert!(result.is_ok(), "{:?}", result.err());
+}
+
+#[test]
+fn sobel_filter() {
+    let _gpu = lock_gpu();
+    let Some(wgpu) = headless_context() else {
+        return;
+    };
+    let input = Texture::new(
+        write_test_pattern(),
+        ColorSpace::Linear,
+        &wgpu,
+        &mut PipelineCache::default(),
+    )
+    .expect("Failed to load pattern");
+    let uniforms = DataBuffer::uniform(
+        ComputeUniforms::new(
+            Mat3::from_cols_array_2d(&[[-1.0, -2.0, -1.0], [0.0, 0.0, 0.0], [1.0, 2.0, 1.0]]),
+            0.5,
+        ),
+        &wgpu.device,
+    );
+    let mut pipeline_cache = PipelineCache::default();
+    let output = compute::compute_filter(
+        &input,
+        &uniforms,
+        &mut pipeline_cache,
+        &wgpu.device,
+        &wgpu.queue,
+    )
+    .expect("Failed to run the filter");
+    let image = read_texture(&output, &wgpu.device, &wgpu.queue, 0).expect("Failed to read back");
+    assert_golden("sobel", &image);
+}
+
+#[test]
+fn pipeline_cache_reuse() {
+    let _gpu = lock_gpu();
+    let Some(wgpu) = headless_context() else {
+        return;
+    };
+    let mut pipeline_cache = PipelineCache::default();
+    let descriptor = || wgpu::ShaderModuleDescriptor {
+        label: Some("Cached"),
+        source: wgpu::ShaderSource::Wgsl(
+            "
+            @vertex
+            fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4f {
+                return vec4f(f32(index), 0.0, 0.0, 1.0);
+            }
+
+            @fragment
+            fn fs_main() -> @location(0) vec4f {
+                return vec4f(1.0);
+            }
+            "
+            .into(),
+        ),
+    };
+    let shader = pipeline_cache.shader(&wgpu.device, descriptor());
+    let again = pipeline_cache.shader(&wgpu.device, descriptor());
+    assert!(Arc::ptr_eq(&shader, &again));
+
+    let builder =
+        || RenderPipeline::builder("Cached", &shader).color_target(Texture::HDR_FORMAT, None);
+    let first = pipeline_cache.render_pipeline(&wgpu.device, builder());
+    let second = pipeline_cache.render_pipeline(&wgpu.device, builder());
+    assert!(Arc::ptr_eq(&first.render_pipeline, &second.render_pipeline));
+    let blended = pipeline_cache.render_pipeline(
+        &wgpu.device,
+        RenderPipeline::builder("Cached", &shader)
+            .color_target(Texture::HDR_FORMAT, Some(wgpu::BlendState::ALPHA_BLENDING)),
+    );
+    assert!(!Arc::ptr_eq(
+        &first.render_pipeline,
+        &blended.render_pipeline
+    ));
+
+    let compute_shader = pipeline_cache.shader(&wgpu.device, wgpu::include_wgsl!("sobel.wgsl"));
+    let compute =
+        pipeline_cache.compute_pipeline(&wgpu.device, &compute_shader, "compute_filter", &[]);
+    let compute_again =
+        pipeline_cache.compute_pipeline(&wgpu.device, &compute_shader, "compute_filter", &[]);
+    assert!(Arc::ptr_eq(&compute, &compute_again));
+    assert_eq!(
+        pipeline_cache.stats,
+        PipelineCacheStats { hits: 2, misses: 3 }
+    );
+}
+
+#[test]
+fn mipmap_and_environment_pipelines_are_cached() {
+    let _gpu = lock_gpu();
+    let Some(wgpu) = headless_context() else {
+        return;
+    };
+    let mut pipeline_cache = PipelineCache::default();
+    for _ in 0..2 {
+        Texture::new(
+            write_test_pattern(),
+            ColorSpace::Linear,
+            &wgpu,
+            &mut pipeline_cache,
+        )
+        .expect("Failed to load pattern");
+    }
+    assert_eq!(
+        pipeline_cache.stats,
+        PipelineCacheStats { hits: 1, misses: 1 }
+    );
+    // Three cube passes and the BRDF lookup table.
+    for _ in 0..2 {
+        Environment::uniform(
+            DEFAULT_AMBIENT,
+            &wgpu.device,
+            &wgpu.queue,
+            &mut pipeline_cache,
+        )
+        .expect("Failed to create the environment");
+    }
+    assert_eq!(
+        pipeline_cache.stats,
+        PipelineCacheStats { hits: 5, misses: 5 }
+    );
+}
+
+#[test]
+fn bind_groups_share_their_layout() {
+    let _gpu = lock_gpu();
+    let Some(wgpu) = headless_context() else {
+        return;
+    };
+    let mut pipeline_cache = PipelineCache::default();
+    let shader = ShaderReflection::from_wgsl("tonemap.wgsl", include_str!("tonemap.wgsl"))
+        .expect("tonemap.wgsl is valid WGSL");
+    let uniforms = DataBuffer::uniform(TonemapUniforms::default(), &wgpu.device);
+    let mut bind_group = |size| {
+        let target = Texture::hdr_target(&wgpu.device, size, size);
+        BindGroup::new(
+            &wgpu.device,
+            &mut pipeline_cache,
+            &shader,
+            0,
+            &[
+                BindResource::Buffer(&uniforms.buffer),
+                BindResource::Texture(&target),
+            ],
+        )
+        .expect("Failed to bind the tonemap resources")
+    };
+    let first = bind_group(4);
+    let second = bind_group(8);
+    assert!(Arc::ptr_eq(
+        &first.bind_group_layout,
+        &second.bind_group_layout
+    ));
+}
+
+#[test]
+fn mipmap_generation() {
+    let _gpu = lock_gpu();
+    let Some(wgpu) = headless_context() else {
+        return;
+    };
+    // `Texture::new` generates the mip chain with `mipmap_generation.wgsl`.
+    let texture = Texture::new(
+        write_test_pattern(),
+        ColorSpace::Linear,
+        &wgpu,
+        &mut PipelineCache::default(),
+    )
+    .expect("Failed to load pattern");
+    for level in [1, 3] {
+        let image = read_texture(&texture.texture, &wgpu.device, &wgpu.queue, level)
+            .expect("Failed to read back");
+        assert_golden(&format!("mipmap_level{level}"), &image);
+    }
+}
+
+#[test]
+fn srgb_mipmaps_average_in_linear_space() {
+    let _gpu = lock_gpu();
+    let Some(wgpu) = headless_context() else {
+        return;
+    };
+    let path = output_dir().join("black_white.png");
+    RgbaImage::from_fn(2, 2, |x, _| {
+        let value = if x == 0 { 0 } else { 255 };
+        Rgba([value, value, value, 255])
+    })
+    .save(&path)
+    .expect("Failed to write black and white pattern");
+    let level1 = |color_space| {
+        let texture = Texture::new(&path, color_space, &wgpu, &mut PipelineCache::default())
+            .expect("Failed to load pattern");
+        read_texture(&texture.texture, &wgpu.device, &wgpu.queue, 1)
+            .expect("Failed to read back")
+            .get_pixel(0, 0)[0]
+    };
+
+    // Linear 0.5 is encoded as 188 in sRGB.
+    assert!(level1(ColorSpace::Srgb).abs_diff(188) <= TOLERANCE);
+    assert!(level1(ColorSpace::Linear).abs_diff(128) <= TOLERANCE);
+}
+
+#[test]
+fn cpu_mipmaps_reject_a_mismatched_image() {
+    let _gpu = lock_gpu();
+    let Some(wgpu) = headless_context() else {
+        return;
+    };
+    let texture = Texture::new(
+        write_test_pattern(),
+        ColorSpace::Linear,
+        &wgpu,
+        &mut PipelineCache::default(),
+    )
+    .expect("Failed to load pattern");
+    let image = RgbaImage::new(3, 5).into();
+    let result = write_mipmaps(&wgpu.queue, &texture.texture, image);
+    assert!(matches!(result, Err(Error::Validation(_))), "{result:?}");
+}
@@ -10,18 +10,23 @@ use egui_wgpu::ScreenDescriptor;
 
 use egui_winit::State;
 
-use glam::{Mat3, Vec3, Vec4};
+use glam::{Mat3, Vec3};
 use wgpu::{CommandEncoder, Device, Queue, TextureFormat, TextureView};
 use winit::event::WindowEvent;
 use winit::window::Window;
 
+use crate::application::{
+    light::{Light, LightKind},
+    material::ShadingModel,
+    pipeline_cache::PipelineCacheStats,
+    shadow::{ShadowSettings, SHADOW_RESOLUTIONS},
+    tonemap::Tonemapping,
+};
+
 #[derive(Default)]
 pub struct GuiState {
     pub clear_color: [f32; 3],
-    pub light_direction1: Vec4,
-    pub light_color1: [f32; 3],
-    pub light_direction2: Vec4,
-    pub light_color2: [f32; 3],
+    pub lights: Vec<Light>,
     pub hardness: f32,
     pub diffuse: f32,
     pub specular: f32,
@@ -29,10 +34,21 @@ pub struct GuiState {
     pub mip_level: f32,
     pub kernel: Mat3,
     pub compute_test: f32,
+    /// Exposure in stops, the HDR scene is scaled by `2^exposure`.
+    pub exposure: f32,
+    pub tonemapping: Tonemapping,
+    pub shadows: ShadowSettings,
+    pub shading_model: ShadingModel,
+    /// Draws the environment instead of the clear color.
+    pub skybox: bool,
+    pub environment_intensity: f32,
+    /// Errors reported since the last time they were cleared.
+    pub errors: Vec<String>,
+    pub pipeline_cache: PipelineCacheStats,
 }
 
 impl GuiState {
-    #[allow(clippy::shadow_unrelated)]
+    #[allow(clippy::shadow_unrelated, clippy::too_many_lines)]
     pub fn gui(&mut self, ui: &Context, delta_time: Duration) {
         egui_extras::install_image_loaders(ui);
         egui::Window::new("Image")
@@ -48,6 +64,19 @@ impl GuiState {
                 });
                 ui.add(egui::Slider::new(&mut self.compute_test, 0.0..=1.0));
             });
+        if !self.errors.is_empty() {
+            egui::Window::new("Errors")
+                .resizable(true)
+                .vscroll(true)
+                .show(ui, |ui| {
+                    for error in &self.errors {
+                        ui.colored_label(egui::Color32::LIGHT_RED, error);
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.errors.clear();
+                    }
+                });
+        }
         egui::Window::new("Lighting")
             .resizable(true)
             .vscroll(true)
@@ -56,17 +85,31 @@ impl GuiState {
                 ui.label("Clear Color");
                 ui.color_edit_button_rgb(&mut self.clear_color);
 
-                ui.label("Light Direction 1");
-                drag_direction(ui, &mut self.light_direction1);
-
-                ui.label("Light Color 1");
-                ui.color_edit_button_rgb(&mut self.light_color1);
+                let mut removed = None;
+                for (i, light) in self.lights.iter_mut().enumerate() {
+                    egui::CollapsingHeader::new(format!("{} Light {}", light.kind, i + 1))
+                        .id_source(i)
+                        .show(ui, |ui| {
+                            edit_light(ui, light);
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                }
+                if let Some(i) = removed {
+                    self.lights.remove(i);
+                }
+                if ui.button("Add Light").clicked() {
+                    self.lights.push(Light::default());
+                }
 
-                ui.label("Light Direction 2");
-                drag_direction(ui, &mut self.light_direction2);
-
-                ui.label("Light Color 2");
-                ui.color_edit_button_rgb(&mut self.light_color2);
+                egui::ComboBox::from_label("Shading model")
+                    .selected_text(self.shading_model.to_string())
+                    .show_ui(ui, |ui| {
+                        for model in ShadingModel::ALL {
+                            ui.selectable_value(&mut self.shading_model, model, model.to_string());
+                        }
+                    });
 
                 ui.label("Hardness");
                 ui.add(egui::Slider::new(&mut self.hardness, 0.0..=100.0));
@@ -83,24 +126,117 @@ impl GuiState {
                 ui.label("Mip Level");
                 ui.add(egui::Slider::new(&mut self.mip_level, 0.0..=12.0));
 
+                ui.label("Exposure (EV)");
+                ui.add(egui::Slider::new(&mut self.exposure, -8.0..=8.0));
+
+                egui::ComboBox::from_label("Tonemapping")
+                    .selected_text(self.tonemapping.to_string())
+                    .show_ui(ui, |ui| {
+                        for tonemapping in Tonemapping::ALL {
+                            ui.selectable_value(
+                                &mut self.tonemapping,
+                                tonemapping,
+                                tonemapping.to_string(),
+                            );
+                        }
+                    });
+
+                egui::CollapsingHeader::new("Environment").show(ui, |ui| {
+                    ui.checkbox(&mut self.skybox, "Skybox");
+                    ui.label("Intensity");
+                    ui.add(egui::Slider::new(
+                        &mut self.environment_intensity,
+                        0.0..=4.0,
+                    ));
+                });
+
+                egui::CollapsingHeader::new("Shadows").show(ui, |ui| {
+                    edit_shadows(ui, &mut self.shadows);
+                });
+
                 ui.label(format!(
                     "Application average {} ms/frame {:.3}",
                     delta_time.as_millis(),
                     delta_time.as_secs_f32()
                 ));
+                ui.label(format!(
+                    "Pipeline cache {} hits, {} misses",
+                    self.pipeline_cache.hits, self.pipeline_cache.misses
+                ));
             });
     }
 }
-fn drag_direction(ui: &mut Ui, v: &mut Vec4) {
-    let v3 = v.truncate();
-    let mut polar = cartesian_to_polar(v3);
+fn edit_light(ui: &mut Ui, light: &mut Light) {
+    egui::ComboBox::from_label("Type")
+        .selected_text(light.kind.to_string())
+        .show_ui(ui, |ui| {
+            for kind in LightKind::ALL {
+                ui.selectable_value(&mut light.kind, kind, kind.to_string());
+            }
+        });
+
+    ui.label("Color");
+    ui.horizontal(|ui| {
+        let mut color = light.color.to_array();
+        ui.color_edit_button_rgb(&mut color);
+        light.color = color.into();
+        ui.add(egui::Slider::new(&mut light.intensity, 0.0..=20.0));
+    });
+
+    if light.kind != LightKind::Point {
+        ui.label("Direction");
+        drag_direction(ui, &mut light.direction);
+    }
+    if light.kind != LightKind::Directional {
+        ui.label("Position");
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut light.position.x).speed(0.05));
+            ui.add(egui::DragValue::new(&mut light.position.y).speed(0.05));
+            ui.add(egui::DragValue::new(&mut light.position.z).speed(0.05));
+        });
+        ui.label("Range (0 for infinite)");
+        ui.add(
+            egui::DragValue::new(&mut light.range)
+                .speed(0.05)
+                .clamp_range(0.0..=f32::MAX),
+        );
+    }
+    if light.kind == LightKind::Spot {
+        ui.label("Cone angles");
+        ui.horizontal(|ui| {
+            ui.drag_angle(&mut light.inner_angle);
+            ui.drag_angle(&mut light.outer_angle);
+        });
+        light.inner_angle = light.inner_angle.clamp(0.0, PI * 0.5);
+        light.outer_angle = light.outer_angle.clamp(light.inner_angle, PI * 0.5);
+    }
+}
+
+fn edit_shadows(ui: &mut Ui, shadows: &mut ShadowSettings) {
+    egui::ComboBox::from_label("Resolution")
+        .selected_text(shadows.resolution.to_string())
+        .show_ui(ui, |ui| {
+            for resolution in SHADOW_RESOLUTIONS {
+                ui.selectable_value(&mut shadows.resolution, resolution, resolution.to_string());
+            }
+        });
+    ui.label("Depth bias");
+    ui.add(egui::Slider::new(&mut shadows.depth_bias, 0.0..=0.01).logarithmic(true));
+    ui.label("Normal bias");
+    ui.add(egui::Slider::new(&mut shadows.normal_bias, 0.0..=0.2));
+    ui.label("Distance");
+    ui.add(egui::Slider::new(&mut shadows.max_distance, 1.0..=100.0).logarithmic(true));
+}
+
+fn drag_direction(ui: &mut Ui, v: &mut Vec3) {
+    let mut polar = cartesian_to_polar(*v);
     ui.horizontal(|ui| {
         ui.drag_angle(&mut polar.x);
         ui.drag_angle(&mut polar.y);
     });
     polar.x = polar.x.clamp(-PI * 0.5, PI * 0.5);
     polar.y = polar.y.clamp(-PI * 0.5, PI * 0.5);
-    *v = polar_to_cartesian(polar).extend(0.0);
+    *v = polar_to_cartesian(polar);
 }
 
 fn cartesian_to_polar(cartesian: Vec3) -> Vec2 {
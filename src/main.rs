@@ -6,13 +6,47 @@
 #![allow(clippy::struct_field_names)]
 mod application;
 mod compute;
+mod error;
+#[cfg(test)]
+mod golden_tests;
 mod gui;
 mod resources;
 
-use application::Application;
-use winit::error::EventLoopError;
+use application::{Application, ApplicationState, SceneDescriptor};
+use resources::{NormalGeneration, NormalWeighting, VertexEncoding};
 
-fn main() -> Result<(), EventLoopError> {
-    let app = Application::new();
-    app.run()
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<_> = std::env::args().skip(1).collect();
+    // Removes a flag from `args` and returns whether it was given.
+    let mut flag = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .map(|i| args.remove(i))
+            .is_some()
+    };
+    let mut scene = SceneDescriptor::default();
+    if flag("--packed-vertices") {
+        scene.load_options.vertex_encoding = VertexEncoding::Packed;
+    }
+    if let (true, NormalGeneration::Smooth { weighting, .. }) = (
+        flag("--area-weighted-normals"),
+        &mut scene.load_options.normals,
+    ) {
+        *weighting = NormalWeighting::Area;
+    }
+    let mut args = args.into_iter();
+    if args.next().as_deref() == Some("--headless") {
+        // Render a single frame offscreen and save it, e.g. for thumbnails.
+        tracing_subscriber::fmt().init();
+        let path = args.next().unwrap_or_else(|| "headless.png".to_owned());
+        let Some(mut state) = ApplicationState::headless(640, 480, &scene)? else {
+            return Err("Failed to find a suitable adapter".into());
+        };
+        state.render();
+        state.save_frame(path)?;
+        return Ok(());
+    }
+    let app = Application::new(&scene)?;
+    app.run()?;
+    Ok(())
 }
@@ -1,15 +1,31 @@
-
 @group(0) @binding(0) var previousMipLevel: texture_2d<f32>;
 @group(0) @binding(1) var nextMipLevel: texture_storage_2d<rgba8unorm,write>;
 
-@compute @workgroup_size(8, 8)
-fn compute_mip_map(@builtin(global_invocation_id) id: vec3<u32>) {
+fn average(id: vec2<u32>) -> vec4<f32> {
     let offset = vec2<u32>(0, 1);
-    let color = (
-        textureLoad(previousMipLevel, 2 * id.xy + offset.xx, 0) +
-        textureLoad(previousMipLevel, 2 * id.xy + offset.xy, 0) +
-        textureLoad(previousMipLevel, 2 * id.xy + offset.yx, 0) +
-        textureLoad(previousMipLevel, 2 * id.xy + offset.yy, 0)
+    return (
+        textureLoad(previousMipLevel, 2 * id + offset.xx, 0) +
+        textureLoad(previousMipLevel, 2 * id + offset.xy, 0) +
+        textureLoad(previousMipLevel, 2 * id + offset.yx, 0) +
+        textureLoad(previousMipLevel, 2 * id + offset.yy, 0)
     ) * 0.25;
-    textureStore(nextMipLevel, id.xy, color);
-}
\ No newline at end of file
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@compute @workgroup_size(8, 8)
+fn compute_mip_map(@builtin(global_invocation_id) id: vec3<u32>) {
+    textureStore(nextMipLevel, id.xy, average(id.xy));
+}
+
+// The previous level is an sRGB texture, so loads are already linear.
+// The storage texture is not, so the average is encoded before storing.
+@compute @workgroup_size(8, 8)
+fn compute_mip_map_srgb(@builtin(global_invocation_id) id: vec3<u32>) {
+    let color = average(id.xy);
+    textureStore(nextMipLevel, id.xy, vec4<f32>(linear_to_srgb(color.rgb), color.a));
+}
@@ -1,14 +1,34 @@
-use std::{fmt::Debug, path::Path};
+mod gltf_import;
+mod mesh_cache;
+mod normals;
+mod optimize;
+mod points;
+mod tangents;
+
+use std::{
+    fmt::Debug,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use glam::{Vec2, Vec3};
-use image::flat::SampleLayout;
-use image::imageops::thumbnail;
-use image::{DynamicImage, FlatSamples, Rgba};
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
 use pollster::FutureExt;
-use tracing::{error, info};
+use tracing::{info, warn};
 use wgpu::Extent3d;
 
-use crate::application::buffer::Buffer;
+use crate::{
+    application::buffer::Buffer,
+    error::{Error, Result},
+};
+
+pub use gltf_import::load_gltf;
+pub use mesh_cache::load_cached_geometry;
+pub use normals::{generate_normals, NormalGeneration, NormalWeighting};
+pub use optimize::optimize_mesh;
+pub use points::{load_points, PointsError};
+pub use tangents::compute_tangents;
 
 fn get_max_mip_level_count(width: u32, height: u32) -> u32 {
     bit_width(u32::max(width, height))
@@ -21,13 +41,59 @@ const fn bit_width(x: u32) -> u32 {
         1 + x.ilog2()
     }
 }
+/// Where the encoded image of a texture comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextureSource {
+    File(PathBuf),
+    /// Encoded image (PNG, JPEG...) embedded in an asset, e.g. a glTF buffer view or data URI.
+    Memory {
+        name: String,
+        bytes: Arc<[u8]>,
+    },
+}
+
+impl TextureSource {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Self::File(path) => path.to_str(),
+            Self::Memory { name, .. } => Some(name),
+        }
+    }
+}
+
+/// How the texels of a texture are interpreted when sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Colors such as albedo or emissive, decoded to linear when sampled.
+    Srgb,
+    /// Data such as normals, roughness or occlusion, sampled as stored.
+    Linear,
+}
+
+impl ColorSpace {
+    pub const fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            Self::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            Self::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
 pub fn load_texture(
-    path: impl AsRef<Path>,
+    source: &TextureSource,
+    color_space: ColorSpace,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-) -> image::ImageResult<(wgpu::Texture, wgpu::TextureView)> {
-    let image = image::open(&path)?;
-    let label = path.as_ref().to_str();
+) -> Result<(wgpu::Texture, wgpu::TextureView)> {
+    let image = match source {
+        TextureSource::File(path) => image::open(path),
+        TextureSource::Memory { bytes, .. } => image::load_from_memory(bytes),
+    }
+    .map_err(|e| Error::Image {
+        name: source.name().unwrap_or_default().to_owned(),
+        source: e,
+    })?;
+    let label = source.name();
     let texture_label = label.map(|s| format!("{s} Texture"));
     let mip_level_count = get_max_mip_level_count(image.width(), image.height());
     let texture_descriptor = wgpu::TextureDescriptor {
@@ -40,9 +106,9 @@ pub fn load_texture(
         mip_level_count,
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba8Unorm,
+        format: color_space.texture_format(),
+        // sRGB formats cannot be storage textures, mip levels are copied in instead.
         usage: wgpu::TextureUsages::TEXTURE_BINDING
-            | wgpu::TextureUsages::STORAGE_BINDING
             | wgpu::TextureUsages::COPY_DST
             | wgpu::TextureUsages::COPY_SRC,
         view_formats: &[],
@@ -55,13 +121,13 @@ pub fn load_texture(
         origin: wgpu::Origin3d::ZERO,
         aspect: wgpu::TextureAspect::All,
     };
-    let source = wgpu::ImageDataLayout {
+    let data_layout = wgpu::ImageDataLayout {
         offset: 0,
         bytes_per_row: Some(4 * texture.size().width),
         rows_per_image: Some(texture.size().height),
     };
     let data = image.into_rgba8().into_raw();
-    queue.write_texture(destination, &data, source, texture.size());
+    queue.write_texture(destination, &data, data_layout, texture.size());
 
     let view_label = label.map(|s| format!("{s} Texture View"));
     let view = texture.create_view(&wgpu::TextureViewDescriptor {
@@ -77,21 +143,144 @@ pub fn load_texture(
     Ok((texture, view))
 }
 
+/// Loads a Radiance HDR (`.hdr`) image, e.g. an equirectangular environment map.
+///
+/// Texels are linear radiance, without the clamping of [`load_texture`].
+pub fn load_hdr(path: impl AsRef<Path>) -> Result<Rgba32FImage> {
+    let path = path.as_ref();
+    let file = std::fs::File::open(path).map_err(|e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    // `image::open` converts HDR images to 8 bits, the decoder keeps the floats.
+    let decode = || {
+        let decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(file))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+        let data = pixels
+            .iter()
+            .flat_map(|p| [p[0], p[1], p[2], 1.0])
+            .collect();
+        Ok((metadata.width, metadata.height, data))
+    };
+    let (width, height, data) = decode().map_err(|e| Error::Image {
+        name: path.display().to_string(),
+        source: e,
+    })?;
+    Rgba32FImage::from_raw(width, height, data).ok_or_else(|| {
+        Error::Validation(format!(
+            "{} decoded to fewer pixels than {width}x{height}",
+            path.display()
+        ))
+    })
+}
+
+/// Where the six faces of a cubemap come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+// The default scene has no skybox, only the golden tests set one.
+#[allow(dead_code)]
+pub enum CubemapSource {
+    /// One image per face, in the order +X, -X, +Y, -Y, +Z, -Z.
+    Faces([PathBuf; 6]),
+    /// A single image with the faces laid out as a horizontal or vertical cross.
+    Cross(PathBuf),
+}
+
+/// Loads the six faces of a cubemap, in the order +X, -X, +Y, -Y, +Z, -Z.
+///
+/// Faces must be square and all the same size.
+pub fn load_cube_faces(paths: &[PathBuf; 6]) -> Result<[RgbaImage; 6]> {
+    let mut faces = Vec::with_capacity(6);
+    for path in paths {
+        let image = image::open(path).map_err(|e| Error::Image {
+            name: path.display().to_string(),
+            source: e,
+        })?;
+        faces.push(image.into_rgba8());
+    }
+    let size = faces[0].width();
+    if let Some((face, path)) = faces
+        .iter()
+        .zip(paths)
+        .find(|(face, _)| face.dimensions() != (size, size))
+    {
+        return Err(Error::Validation(format!(
+            "cube face {} is {}x{}, expected {size}x{size}",
+            path.display(),
+            face.width(),
+            face.height()
+        )));
+    }
+    Ok(faces.try_into().expect("six faces were loaded"))
+}
+
+/// Loads a cubemap laid out as a cross, see [`split_cross`].
+pub fn load_cross(path: impl AsRef<Path>) -> Result<[RgbaImage; 6]> {
+    let path = path.as_ref();
+    let image = image::open(path).map_err(|e| Error::Image {
+        name: path.display().to_string(),
+        source: e,
+    })?;
+    split_cross(&image.into_rgba8())
+}
+
+/// Cuts the faces, in the order +X, -X, +Y, -Y, +Z, -Z, out of a cross layout.
+///
+/// A horizontal cross is 4x3 faces with -X, +Z, +X, -Z in the middle row and
+/// +Y, -Y above and below +Z. A vertical cross is 3x4 faces with -Z under -Y,
+/// upside down.
+pub fn split_cross(image: &RgbaImage) -> Result<[RgbaImage; 6]> {
+    let (width, height) = image.dimensions();
+    // Face positions in the cross, in face units.
+    let (size, positions) = if width * 3 == height * 4 {
+        (width / 4, [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)])
+    } else if width * 4 == height * 3 {
+        (width / 3, [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (1, 3)])
+    } else {
+        return Err(Error::Validation(format!(
+            "cross cubemap is {width}x{height}, expected a 4:3 or 3:4 image"
+        )));
+    };
+    let vertical = height > width;
+    Ok(positions.map(|(x, y)| {
+        let face = image::imageops::crop_imm(image, x * size, y * size, size, size).to_image();
+        if vertical && (x, y) == (1, 3) {
+            image::imageops::rotate180(&face)
+        } else {
+            face
+        }
+    }))
+}
+
 pub fn save_texture(
     path: impl AsRef<Path>,
     texture: &wgpu::Texture,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     mip_level: u32,
-) {
-    let width = texture.width() / (1 << mip_level); // pow(mip_level,2)
-    let height = texture.height() / (1 << mip_level);
+) -> Result<()> {
+    let image = read_texture(texture, device, queue, mip_level)?;
+    image.save(&path).map_err(|e| Error::Image {
+        name: path.as_ref().display().to_string(),
+        source: e,
+    })
+}
+
+/// Copies one mip level of an 8-bit RGBA texture back to the CPU.
+pub fn read_texture(
+    texture: &wgpu::Texture,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mip_level: u32,
+) -> Result<RgbaImage> {
+    let width = (texture.width() >> mip_level).max(1);
+    let height = (texture.height() >> mip_level).max(1);
     let channels = 4;
     let component_byte_size = 1;
     let bytes_per_row = width * channels * component_byte_size;
-    // Special case: WebGPU spec forbids texture-to-buffer copy with a
-    // bytesPerRow lower than 256 so we first copy to a temporary texture.
-    let padded_bytes_per_row = bytes_per_row.max(256);
+    // WebGPU requires texture-to-buffer copies to use a bytesPerRow that is a
+    // multiple of 256, so rows are padded in the buffer and stripped afterwards.
+    let padded_bytes_per_row = bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
     let pixel_buffer = Buffer::new(
         device,
         u64::from(padded_bytes_per_row * height),
@@ -134,32 +323,39 @@ pub fn save_texture(
     device.poll(wgpu::Maintain::Wait);
     receiver
         .block_on()
-        .expect("communication failed")
-        .expect("buffer reading failed");
+        .map_err(|e| Error::Readback(e.to_string()))?
+        .map_err(|e| Error::Readback(e.to_string()))?;
     let pixels: &[u8] = &pixel_buffer.buffer.slice(..).get_mapped_range();
-
-    let layout = SampleLayout::row_major_packed(4, width, height);
-    let buffer = FlatSamples {
-        samples: pixels,
-        layout,
-        color_hint: None,
-    };
     info!("{width}x{height} padded: {padded_bytes_per_row} ");
 
-    let view = match buffer.as_view::<Rgba<u8>>() {
-        Err(e) => {
-            error!("{e}");
-            return;
-        } // Invalid layout.
-        Ok(view) => view,
-    };
-    thumbnail(&view, width, height)
-        .save(path)
-        .expect("Unable to save");
+    let data = pixels
+        .chunks_exact(padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..bytes_per_row as usize])
+        .copied()
+        .collect();
+    RgbaImage::from_raw(width, height, data).ok_or_else(|| {
+        Error::Readback(format!(
+            "texture data does not match a {width}x{height} RGBA image"
+        ))
+    })
 }
 
-#[allow(clippy::similar_names)]
-pub fn write_mipmaps(queue: &wgpu::Queue, texture: &wgpu::Texture, image: DynamicImage) {
+#[allow(clippy::similar_names, dead_code)]
+pub fn write_mipmaps(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    image: DynamicImage,
+) -> Result<()> {
+    let size = texture.size();
+    if (image.width(), image.height()) != (size.width, size.height) {
+        return Err(Error::Validation(format!(
+            "{}x{} image does not fit a {}x{} texture",
+            image.width(),
+            image.height(),
+            size.width,
+            size.height
+        )));
+    }
     let data = image.into_rgba8().into_raw();
     let mut mip_level_size = texture.size();
     let mut previous_level_pixels = vec![];
@@ -228,59 +424,280 @@ pub fn write_mipmaps(queue: &wgpu::Queue, texture: &wgpu::Texture, image: Dynami
         mip_level_size.height /= 2;
         previous_level_pixels = pixels;
     }
+    Ok(())
 }
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, VertexAttributeLayout)]
 #[repr(C)]
 pub struct VertexAttribute {
     pub position: Vec3,
+    // The tangent and its handedness are read as a single `vec4f`.
+    #[vertex(format = Float32x4)]
     pub tangent: Vec3,
-    pub bitangent: Vec3,
+    /// Sign of the bitangent, `bitangent = handedness * cross(normal, tangent)`.
+    #[vertex(skip)]
+    pub handedness: f32,
     pub normal: Vec3,
     pub color: Vec3,
     pub uv: Vec2,
 }
 
-fn compute_tangent_frame(face: [VertexAttribute; 3], expected_normal: Vec3) -> (Vec3, Vec3) {
-    let e1_pos = face[1].position - face[0].position;
-    let e2_pos = face[2].position - face[0].position;
-
-    let e1_uv = face[1].uv - face[0].uv;
-    let e2_uv = face[2].uv - face[0].uv;
+/// A `VertexAttribute` in 28 bytes instead of 60, read by `vs_packed` of `shader.wgsl`.
+///
+/// Colors are clamped to [0, 1] and UVs lose precision far from the origin.
+#[derive(
+    Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable, VertexAttributeLayout,
+)]
+#[repr(C)]
+pub struct PackedVertex {
+    pub position: Vec3,
+    /// Tangent in xyz and its handedness in w.
+    pub tangent: [i8; 4],
+    /// Octahedral encoding of the normal, see [`octahedral_encode`].
+    pub normal: [i16; 2],
+    /// RGB with an opaque alpha.
+    pub color: [u8; 4],
+    /// Half floats.
+    #[vertex(format = Float16x2)]
+    pub uv: [u16; 2],
+}
 
-    let mut tangent = (e1_pos * e2_uv.y - e2_pos * e1_uv.y).normalize();
-    let mut bitangent = (e2_pos * e1_uv.x - e1_pos * e2_uv.x).normalize();
-    let mut normal = tangent.cross(bitangent);
+impl PackedVertex {
+    // The color is clamped to positive values.
+    #[allow(clippy::cast_sign_loss)]
+    pub fn new(vertex: &VertexAttribute) -> Self {
+        let tangent = vertex
+            .tangent
+            .normalize_or_zero()
+            .extend(vertex.handedness.signum());
+        let color = vertex.color.clamp(Vec3::ZERO, Vec3::ONE).extend(1.0);
+        Self {
+            position: vertex.position,
+            tangent: tangent.to_array().map(|x| (x * 127.0).round() as i8),
+            normal: octahedral_encode(vertex.normal)
+                .to_array()
+                .map(|x| (x * 32767.0).round() as i16),
+            color: color.to_array().map(|x| (x * 255.0).round() as u8),
+            uv: vertex
+                .uv
+                .to_array()
+                .map(|x| half::f16::from_f32(x).to_bits()),
+        }
+    }
+}
 
-    if normal.dot(expected_normal) < 0.0 {
-        tangent = -tangent;
+/// Maps a direction onto the [-1, 1] square: the upper half of the octahedron
+/// projected straight down, the lower half folded over its edges.
+pub fn octahedral_encode(direction: Vec3) -> Vec2 {
+    let n = direction / direction.abs().element_sum();
+    if n.z >= 0.0 {
+        n.truncate()
+    } else {
+        (Vec2::ONE - Vec2::new(n.y, n.x).abs()) * n.truncate().signum()
     }
+}
 
-    normal = expected_normal;
-    tangent = (tangent - tangent.dot(normal) * normal).normalize();
-    bitangent = normal.cross(tangent);
+/// Inverse of [`octahedral_encode`], mirrors `octahedral_decode` of `shader.wgsl`.
+#[cfg(test)]
+pub fn octahedral_decode(encoded: Vec2) -> Vec3 {
+    let mut n = encoded.extend(1.0 - encoded.abs().element_sum());
+    let t = (-n.z).max(0.0);
+    n.x -= t.copysign(n.x);
+    n.y -= t.copysign(n.y);
+    n.normalize()
+}
 
-    (tangent, bitangent)
+/// How the vertices of a mesh are stored on the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VertexEncoding {
+    /// As `VertexAttribute`.
+    #[default]
+    Full,
+    /// As `PackedVertex`.
+    Packed,
 }
 
-impl VertexAttributeLayout for VertexAttribute {
-    fn layout() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![0=>Float32x3,1=>Float32x3,2=>Float32x3,3=>Float32x3,4=>Float32x3,5=>Float32x2];
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &ATTRIBUTES,
+impl VertexEncoding {
+    pub fn layout(self) -> wgpu::VertexBufferLayout<'static> {
+        match self {
+            Self::Full => VertexAttribute::layout(),
+            Self::Packed => PackedVertex::layout(),
+        }
+    }
+
+    /// Entry point of `shader.wgsl` reading vertices of this encoding.
+    pub const fn vertex_entry_point(self) -> &'static str {
+        match self {
+            Self::Full => "vs_main",
+            Self::Packed => "vs_packed",
         }
     }
 }
 
+/// Vertex buffer layout of a vertex type, usually derived.
 pub trait VertexAttributeLayout {
     fn layout() -> wgpu::VertexBufferLayout<'static>;
 }
+pub use wgpu_cpp_derive::VertexAttributeLayout;
+
+/// A range of a `Mesh`'s index buffer drawn with its own material.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submesh {
+    pub name: String,
+    /// Range in `Mesh::indices`, the indices are relative to `base_vertex`.
+    pub indices: Range<u32>,
+    pub base_vertex: i32,
+    pub material_id: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<VertexAttribute>,
+    pub indices: Vec<u32>,
+    pub submeshes: Vec<Submesh>,
+    /// Materials referenced by `Submesh::material_id`.
+    pub materials: Vec<MaterialDescriptor>,
+}
 
-pub fn load_geometry(path: impl AsRef<Path> + Debug) -> (Vec<VertexAttribute>, Vec<u32>) {
-    let (models, _) = tobj::load_obj(
-        path,
+/// A material parsed from an MTL file or a glTF document.
+///
+/// MTL files fill the Phong terms, glTF files the metallic-roughness ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialDescriptor {
+    pub name: String,
+    /// `map_Kd` or the glTF base color texture
+    pub diffuse_texture: Option<TextureSource>,
+    pub normal_texture: Option<TextureSource>,
+    /// `map_Ks`
+    pub specular_texture: Option<TextureSource>,
+    /// `Kd` or the glTF base color factor
+    pub diffuse: Vec3,
+    /// `Ks`
+    pub specular: Vec3,
+    /// `Ns`, `None` lets the renderer pick its global hardness.
+    pub shininess: Option<f32>,
+    /// `Pm` or the glTF metallic factor
+    pub metallic: f32,
+    /// `Pr` or the glTF roughness factor
+    pub roughness: f32,
+    /// Metalness in the blue channel, roughness in the green one.
+    pub metallic_roughness_texture: Option<TextureSource>,
+    /// Ambient occlusion in the red channel.
+    pub occlusion_texture: Option<TextureSource>,
+    /// How much of `occlusion_texture` is applied, between 0 and 1.
+    pub occlusion_strength: f32,
+    /// `Ke` or the glTF emissive factor
+    pub emissive: Vec3,
+    pub emissive_texture: Option<TextureSource>,
+}
+
+impl Default for MaterialDescriptor {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            diffuse_texture: None,
+            normal_texture: None,
+            specular_texture: None,
+            diffuse: Vec3::ONE,
+            specular: Vec3::ONE,
+            shininess: None,
+            metallic: 0.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive: Vec3::ZERO,
+            emissive_texture: None,
+        }
+    }
+}
+
+impl MaterialDescriptor {
+    fn from_mtl(material: &tobj::Material, directory: &Path) -> Self {
+        // Map statements may carry options before the file name, e.g. `map_Bump -bm 0.3 n.png`.
+        let texture_path = |map: Option<&str>| {
+            map.and_then(|map| map.split_whitespace().last())
+                .map(|file_name| TextureSource::File(directory.join(file_name)))
+        };
+        // tobj keeps the PBR extension statements as unknown parameters.
+        let parameter = |key: &str| material.unknown_param.get(key).map(String::as_str);
+        let scalar = |key: &str| parameter(key).and_then(|value| value.trim().parse::<f32>().ok());
+        let defaults = Self::default();
+        Self {
+            name: material.name.clone(),
+            diffuse_texture: texture_path(material.diffuse_texture.as_deref()),
+            normal_texture: texture_path(material.normal_texture.as_deref()),
+            specular_texture: texture_path(material.specular_texture.as_deref()),
+            diffuse: material.diffuse.map_or(Vec3::ONE, Vec3::from),
+            specular: material.specular.map_or(Vec3::ONE, Vec3::from),
+            shininess: material.shininess,
+            metallic: scalar("Pm").unwrap_or(defaults.metallic),
+            roughness: scalar("Pr").unwrap_or(defaults.roughness),
+            emissive: parameter("Ke")
+                .and_then(|value| {
+                    let values: Vec<f32> = value
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .ok()?;
+                    (values.len() == 3).then(|| Vec3::from_slice(&values))
+                })
+                .unwrap_or(defaults.emissive),
+            emissive_texture: texture_path(parameter("map_Ke")),
+            ..defaults
+        }
+    }
+}
+
+/// Options applied when loading a mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LoadOptions {
+    /// Used for OBJ objects without normals. glTF requires flat normals instead.
+    pub normals: NormalGeneration,
+    /// Meshes are loaded as `VertexAttribute`s and encoded when uploaded.
+    pub vertex_encoding: VertexEncoding,
+    /// Runs `optimize_mesh` on the loaded mesh.
+    pub optimize: bool,
+    /// Loads OBJ files through a cache next to them, see `load_cached_geometry`.
+    pub cache: bool,
+}
+
+/// Loads a glTF (`.gltf`, `.glb`), `[points]` (`.txt`) or OBJ file depending on its extension.
+pub fn load_mesh(path: impl AsRef<Path> + Debug, options: LoadOptions) -> Result<Mesh> {
+    let mut mesh = match path.as_ref().extension().and_then(|e| e.to_str()) {
+        Some("gltf" | "glb") => load_gltf(path)?,
+        Some("txt") => {
+            let (vertices, indices) = load_points(&path)?;
+            let name = path.as_ref().file_stem().unwrap_or_default();
+            Mesh {
+                submeshes: vec![Submesh {
+                    name: name.to_string_lossy().into_owned(),
+                    indices: 0..indices.len() as u32,
+                    base_vertex: 0,
+                    material_id: None,
+                }],
+                vertices,
+                indices,
+                materials: vec![],
+            }
+        }
+        _ if options.cache => load_cached_geometry(path, options)?,
+        _ => load_geometry(path, options)?,
+    };
+    if options.optimize {
+        optimize_mesh(&mut mesh)?;
+    }
+    Ok(mesh)
+}
+
+#[allow(clippy::too_many_lines)]
+pub fn load_geometry(path: impl AsRef<Path> + Debug, options: LoadOptions) -> Result<Mesh> {
+    let directory = path
+        .as_ref()
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let (models, materials) = tobj::load_obj(
+        &path,
         &tobj::LoadOptions {
             single_index: true,
             triangulate: true,
@@ -288,11 +705,37 @@ pub fn load_geometry(path: impl AsRef<Path> + Debug) -> (Vec<VertexAttribute>, V
             ignore_lines: true,
         },
     )
-    .expect("Failed to OBJ load file");
+    .map_err(|e| Error::Obj {
+        path: path.as_ref().to_owned(),
+        source: e,
+    })?;
+    let materials = match materials {
+        Ok(materials) => materials
+            .iter()
+            .map(|material| MaterialDescriptor::from_mtl(material, &directory))
+            .collect(),
+        Err(e) => {
+            warn!("Failed to load materials of {path:?}: {e}");
+            vec![]
+        }
+    };
     let mut vertices = vec![];
     let mut indices: Vec<u32> = vec![];
+    let mut submeshes = Vec::with_capacity(models.len());
+    let mut without_normals = vec![];
     for model in &models {
         let mesh = &model.mesh;
+        let first_index = indices.len() as u32;
+        submeshes.push(Submesh {
+            name: model.name.clone(),
+            indices: first_index..first_index + mesh.indices.len() as u32,
+            base_vertex: i32::try_from(vertices.len())
+                .map_err(|e| Error::Validation(format!("{path:?} has too many vertices: {e}")))?,
+            material_id: mesh.material_id,
+        });
+        if mesh.normals.is_empty() {
+            without_normals.extend(submeshes.last().cloned());
+        }
         indices.extend(&mesh.indices);
         let mut positions = Vec::with_capacity(mesh.positions.len() / 3);
         for p in mesh.positions.chunks_exact(3) {
@@ -331,8 +774,8 @@ pub fn load_geometry(path: impl AsRef<Path> + Debug) -> (Vec<VertexAttribute>, V
         vertices.extend(positions.into_iter().zip(normals).zip(colors).zip(uvs).map(
             |(((p, n), c), t)| VertexAttribute {
                 position: p,
-                tangent: Vec3::Y,
-                bitangent: Vec3::Z,
+                tangent: Vec3::X,
+                handedness: 1.0,
                 normal: n,
                 color: c,
                 uv: t,
@@ -340,17 +783,379 @@ pub fn load_geometry(path: impl AsRef<Path> + Debug) -> (Vec<VertexAttribute>, V
         ));
     }
 
-    for i in indices.chunks_exact(3) {
-        let v1 = vertices[i[0] as usize];
-        let v2 = vertices[i[1] as usize];
-        let v3 = vertices[i[2] as usize];
-        for j in 0..3 {
-            let v = &mut vertices[i[j] as usize];
-            let (tangent, bitangent) = compute_tangent_frame([v1, v2, v3], v.normal);
-            v.tangent = tangent;
-            v.bitangent = bitangent;
+    generate_normals(
+        &mut vertices,
+        &mut indices,
+        &without_normals,
+        options.normals,
+    )?;
+    compute_tangents(&mut vertices, &mut indices, &submeshes)?;
+
+    Ok(Mesh {
+        vertices,
+        indices,
+        submeshes,
+        materials,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec4;
+
+    #[test]
+    fn multi_object_obj_keeps_indices_per_submesh() {
+        let path = std::env::temp_dir().join("wgpu-cpp-two-triangles.obj");
+        std::fs::write(
+            &path,
+            "o First\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n\
+             o Second\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\nvn 0 0 1\n\
+             f 4//2 5//2 6//2\nf 4//2 6//2 7//2\n",
+        )
+        .unwrap();
+        let mesh = load_geometry(&path, LoadOptions::default()).unwrap();
+
+        assert_eq!(mesh.vertices.len(), 7);
+        let names: Vec<_> = mesh.submeshes.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["First", "Second"]);
+        let second = &mesh.submeshes[1];
+        assert_eq!(second.indices, 3..9);
+        assert_eq!(second.base_vertex, 3);
+        for submesh in &mesh.submeshes {
+            for &index in
+                &mesh.indices[submesh.indices.start as usize..submesh.indices.end as usize]
+            {
+                let base_vertex = usize::try_from(submesh.base_vertex).unwrap();
+                let vertex = mesh.vertices[base_vertex + index as usize];
+                let expected_z = if submesh.name == "First" { 0.0 } else { 1.0 };
+                assert!((vertex.position.z - expected_z).abs() < f32::EPSILON);
+            }
         }
     }
 
-    (vertices, indices)
+    #[test]
+    fn mtl_materials_resolve_texture_paths() {
+        let directory = std::env::temp_dir().join("wgpu-cpp-mtl");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("quad.mtl"),
+            "newmtl Painted\nKd 0.5 0.25 1.0\nKs 0.1 0.1 0.1\nNs 20\n\
+             map_Kd albedo.jpg\nmap_Bump -bm 0.3 normals.png\n",
+        )
+        .unwrap();
+        let path = directory.join("quad.obj");
+        std::fs::write(
+            &path,
+            "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl Painted\nf 1 2 3\n",
+        )
+        .unwrap();
+        let mesh = load_geometry(&path, LoadOptions::default()).unwrap();
+
+        assert_eq!(mesh.submeshes[0].material_id, Some(0));
+        let material = &mesh.materials[0];
+        assert_eq!(material.name, "Painted");
+        assert_eq!(material.diffuse, Vec3::new(0.5, 0.25, 1.0));
+        assert_eq!(material.shininess, Some(20.0));
+        assert_eq!(
+            material.diffuse_texture,
+            Some(TextureSource::File(directory.join("albedo.jpg")))
+        );
+        assert_eq!(
+            material.normal_texture,
+            Some(TextureSource::File(directory.join("normals.png")))
+        );
+        assert_eq!(material.specular_texture, None);
+    }
+
+    #[test]
+    fn mtl_pbr_parameters() {
+        let directory = std::env::temp_dir().join("wgpu-cpp-mtl-pbr");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("quad.mtl"),
+            "newmtl Metal\nKd 0.9 0.6 0.2\nPm 1.0\nPr 0.25\nKe 0.5 0.0 0.0\nmap_Ke glow.png\n\
+             newmtl Plain\nKd 1 1 1\n",
+        )
+        .unwrap();
+        let path = directory.join("quad.obj");
+        std::fs::write(
+            &path,
+            "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl Metal\nf 1 2 3\n",
+        )
+        .unwrap();
+        let mesh = load_geometry(&path, LoadOptions::default()).unwrap();
+
+        let metal = &mesh.materials[0];
+        assert!((metal.metallic - 1.0).abs() < f32::EPSILON);
+        assert!((metal.roughness - 0.25).abs() < f32::EPSILON);
+        assert_eq!(metal.emissive, Vec3::new(0.5, 0.0, 0.0));
+        assert_eq!(
+            metal.emissive_texture,
+            Some(TextureSource::File(directory.join("glow.png")))
+        );
+        let plain = &mesh.materials[1];
+        assert!(plain.metallic.abs() < f32::EPSILON);
+        assert!((plain.roughness - 1.0).abs() < f32::EPSILON);
+        assert_eq!(plain.emissive, Vec3::ZERO);
+    }
+
+    #[test]
+    fn hdr_images_keep_values_above_one() {
+        let path = std::env::temp_dir().join("wgpu-cpp-sky.hdr");
+        let pixels = [image::Rgb([8.0, 0.5, 0.25]), image::Rgb([0.0, 1.0, 2.0])];
+        image::codecs::hdr::HdrEncoder::new(std::fs::File::create(&path).unwrap())
+            .encode(&pixels, 2, 1)
+            .unwrap();
+        let image = load_hdr(&path).unwrap();
+
+        assert_eq!(image.dimensions(), (2, 1));
+        let pixel = |x| Vec4::from_array(image.get_pixel(x, 0).0);
+        assert!(pixel(0).abs_diff_eq(Vec4::new(8.0, 0.5, 0.25, 1.0), 1e-6));
+        assert!(pixel(1).abs_diff_eq(Vec4::new(0.0, 1.0, 2.0, 1.0), 1e-6));
+        assert!(matches!(
+            load_hdr("resources/missing.hdr"),
+            Err(Error::Io { .. })
+        ));
+    }
+
+    /// Each face filled with its index, as the red channel.
+    fn cross(faces_x: u32, faces_y: u32, positions: [(u32, u32); 6]) -> RgbaImage {
+        let size = 4;
+        RgbaImage::from_fn(faces_x * size, faces_y * size, |x, y| {
+            let face = positions.iter().position(|&p| p == (x / size, y / size));
+            // The green channel tells which way up the face is.
+            face.map_or(image::Rgba([255, 0, 0, 255]), |face| {
+                image::Rgba([face as u8, (y % size) as u8, 0, 255])
+            })
+        })
+    }
+
+    #[test]
+    fn cross_layouts_split_into_faces() {
+        let horizontal = cross(4, 3, [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)]);
+        let vertical = cross(3, 4, [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (1, 3)]);
+        for (image, flipped_face) in [(horizontal, None), (vertical, Some(5))] {
+            let faces = split_cross(&image).unwrap();
+            for (index, face) in faces.iter().enumerate() {
+                assert_eq!(face.dimensions(), (4, 4));
+                assert_eq!(face.get_pixel(0, 0)[0], index as u8);
+                let top_row = if flipped_face == Some(index) { 3 } else { 0 };
+                assert_eq!(face.get_pixel(0, 0)[1], top_row);
+            }
+        }
+
+        let square = RgbaImage::new(8, 8);
+        assert!(matches!(split_cross(&square), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn cube_faces_must_match() {
+        let dir = std::env::temp_dir();
+        let paths = [0, 1, 2, 3, 4, 5].map(|face| dir.join(format!("wgpu-cpp-face{face}.png")));
+        for (face, path) in paths.iter().enumerate() {
+            let size = if face == 3 { 8 } else { 4 };
+            RgbaImage::new(size, size).save(path).unwrap();
+        }
+        let error = load_cube_faces(&paths).unwrap_err();
+        assert!(matches!(error, Error::Validation(_)), "{error}");
+        assert!(error.to_string().contains("wgpu-cpp-face3.png"));
+
+        RgbaImage::new(4, 4).save(&paths[3]).unwrap();
+        assert!(load_cube_faces(&paths).is_ok());
+    }
+
+    #[test]
+    fn missing_assets_are_errors() {
+        let options = LoadOptions::default();
+
+        let obj = load_mesh("resources/missing.obj", options).unwrap_err();
+        assert!(matches!(obj, Error::Obj { .. }), "{obj}");
+        let gltf = load_mesh("resources/missing.gltf", options).unwrap_err();
+        assert!(matches!(gltf, Error::Io { .. }), "{gltf}");
+        assert!(gltf.to_string().contains("resources/missing.gltf"));
+
+        let path = std::env::temp_dir().join("wgpu-cpp-broken.txt");
+        std::fs::write(&path, "[points]\n0 0 1 1 1\n[indices]\n0 1 2\n").unwrap();
+        let points = load_mesh(&path, options).unwrap_err();
+        assert!(
+            matches!(points, Error::Points { ref source, .. } if source.line == 4),
+            "{points}"
+        );
+    }
+
+    #[test]
+    fn derived_vertex_layout_reads_tangent_and_handedness_together() {
+        let layout = VertexAttribute::layout();
+        assert_eq!(layout.array_stride, 60);
+        assert_eq!(layout.step_mode, wgpu::VertexStepMode::Vertex);
+        assert_eq!(
+            layout.attributes,
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4, 2 => Float32x3, 3 => Float32x3, 4 => Float32x2]
+        );
+    }
+
+    #[derive(Clone, Copy, VertexAttributeLayout)]
+    #[vertex(instance, crate = super)]
+    #[repr(C)]
+    // Only its layout is used.
+    #[allow(dead_code)]
+    struct Instance {
+        #[location = 5]
+        transform_x: [f32; 4],
+        #[vertex(skip)]
+        id: u32,
+        color: [u8; 4],
+        normal: [i16; 2],
+        layer: u32,
+    }
+
+    #[test]
+    fn derived_instance_layout() {
+        let layout = Instance::layout();
+        assert_eq!(layout.step_mode, wgpu::VertexStepMode::Instance);
+        let attributes: Vec<_> = layout
+            .attributes
+            .iter()
+            .map(|a| (a.shader_location, a.offset, a.format))
+            .collect();
+        assert_eq!(
+            attributes,
+            [
+                (5, 0, wgpu::VertexFormat::Float32x4),
+                (6, 20, wgpu::VertexFormat::Unorm8x4),
+                (7, 24, wgpu::VertexFormat::Snorm16x2),
+                (8, 28, wgpu::VertexFormat::Uint32),
+            ]
+        );
+    }
+
+    #[test]
+    fn packed_vertex_layout() {
+        let layout = PackedVertex::layout();
+        assert_eq!(layout.array_stride, 28);
+        assert_eq!(
+            layout.attributes,
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Snorm8x4, 2 => Snorm16x2, 3 => Unorm8x4, 4 => Float16x2]
+        );
+    }
+
+    #[test]
+    fn octahedral_normals_round_trip() {
+        let mut directions = vec![
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ];
+        for i in 0..64 {
+            let angle = i as f32 * 0.4;
+            let z = (i as f32 / 32.0) - 1.0;
+            directions.push(Vec3::new(angle.cos(), angle.sin(), z).normalize());
+        }
+        for direction in directions {
+            let decoded = octahedral_decode(octahedral_encode(direction));
+            assert!(
+                decoded.abs_diff_eq(direction, 1e-6),
+                "{direction} -> {decoded}"
+            );
+
+            let vertex = VertexAttribute {
+                position: Vec3::ZERO,
+                tangent: Vec3::X,
+                handedness: -1.0,
+                normal: direction,
+                color: Vec3::ONE,
+                uv: Vec2::ZERO,
+            };
+            let packed = PackedVertex::new(&vertex)
+                .normal
+                .map(|x| f32::from(x) / 32767.0);
+            let quantized = octahedral_decode(Vec2::from_array(packed));
+            assert!(
+                quantized.angle_between(direction) < 1e-3,
+                "{direction} -> {quantized}"
+            );
+        }
+    }
+
+    #[test]
+    fn mirrored_uv_copies_go_after_every_submesh() {
+        let vertex = |x, y, u, v| VertexAttribute {
+            position: Vec3::new(x, y, 0.0),
+            tangent: Vec3::ZERO,
+            handedness: 0.0,
+            normal: Vec3::Z,
+            color: Vec3::ONE,
+            uv: Vec2::new(u, v),
+        };
+        let mut vertices = vec![
+            // Two faces sharing the edge 0-2, the second mirrors U.
+            vertex(0.0, 0.0, 0.0, 1.0),
+            vertex(1.0, 0.0, 1.0, 1.0),
+            vertex(0.0, 1.0, 0.0, 0.0),
+            vertex(-1.0, 0.0, 1.0, 1.0),
+            vertex(0.0, 0.0, 0.0, 1.0),
+            vertex(1.0, 0.0, 1.0, 1.0),
+            vertex(0.0, 1.0, 0.0, 0.0),
+        ];
+        let mut indices = vec![0, 1, 2, 0, 2, 3, 0, 1, 2];
+        let submesh = |name: &str, range, base_vertex| Submesh {
+            name: name.to_owned(),
+            indices: range,
+            base_vertex,
+            material_id: None,
+        };
+        let submeshes = [submesh("Mirrored", 0..6, 0), submesh("Last", 6..9, 4)];
+        compute_tangents(&mut vertices, &mut indices, &submeshes).unwrap();
+
+        assert_eq!(vertices.len(), 9);
+        assert_eq!(indices, [0, 1, 2, 7, 8, 3, 0, 1, 2]);
+        assert_eq!(vertices[7].position, vertices[0].position);
+        assert_eq!(vertices[8].position, vertices[2].position);
+        assert!(vertices[..3].iter().all(|v| v.handedness > 0.0));
+        assert!(vertices[7..].iter().all(|v| v.handedness < 0.0));
+
+        let negative = [submesh("Negative", 0..3, -1)];
+        assert!(matches!(
+            compute_tangents(&mut vertices, &mut indices, &negative),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn packed_vertices_quantize_attributes() {
+        let vertex = VertexAttribute {
+            position: Vec3::new(1.5, -2.0, 0.25),
+            tangent: Vec3::new(0.0, 3.0, 4.0),
+            handedness: -1.0,
+            normal: Vec3::X,
+            color: Vec3::new(1.5, 0.5, -1.0),
+            uv: Vec2::new(0.25, 1.0 / 3.0),
+        };
+        let packed = PackedVertex::new(&vertex);
+
+        assert_eq!(packed.position, vertex.position);
+        assert_eq!(packed.tangent, [0, 76, 102, -127]);
+        assert_eq!(packed.color, [255, 128, 0, 255]);
+        let uv = packed.uv.map(|x| half::f16::from_bits(x).to_f32());
+        assert!(Vec2::from_array(uv).abs_diff_eq(vertex.uv, 1e-3));
+        assert!((uv[0] - 0.25).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn packed_encoding_takes_less_than_half_the_memory() {
+        let mesh = load_mesh("resources/cylinder.obj", LoadOptions::default()).unwrap();
+        let packed: Vec<_> = mesh.vertices.iter().map(PackedVertex::new).collect();
+        let full_size = std::mem::size_of_val(mesh.vertices.as_slice());
+        let packed_size = std::mem::size_of_val(packed.as_slice());
+        assert_eq!(full_size, 60 * mesh.vertices.len());
+        assert_eq!(packed_size, 28 * mesh.vertices.len());
+        assert_eq!(
+            [VertexEncoding::Full, VertexEncoding::Packed].map(|e| e.layout().array_stride),
+            [60, 28]
+        );
+    }
 }
@@ -0,0 +1,355 @@
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use base64::Engine;
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use tracing::warn;
+
+use super::{
+    compute_tangents, generate_normals, MaterialDescriptor, Mesh, NormalGeneration, Submesh,
+    TextureSource, VertexAttribute,
+};
+use crate::error::{Error, Result};
+
+/// Loads the default scene of a glTF 2.0 file (`.gltf` or `.glb`).
+///
+/// Node transforms are baked into the vertices, so every primitive becomes a
+/// `Submesh` in world space. Buffers and images may be external files, data
+/// URIs or, for `.glb`, the binary chunk.
+pub fn load_gltf(path: impl AsRef<Path> + Debug) -> Result<Mesh> {
+    let path = path.as_ref();
+    let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let data = std::fs::read(path).map_err(|e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    let gltf = gltf::Gltf::from_slice(&data).map_err(|e| Error::Gltf {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    let buffers = gltf
+        .buffers()
+        .map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone().ok_or_else(|| {
+                Error::Validation(format!("{} has no binary chunk", path.display()))
+            }),
+            gltf::buffer::Source::Uri(uri) => read_uri(uri, &directory),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut mesh = Mesh {
+        materials: gltf
+            .materials()
+            .map(|material| material_descriptor(&material, &buffers, &directory))
+            .collect::<Result<_>>()?,
+        ..Default::default()
+    };
+    let scene = gltf.default_scene().or_else(|| gltf.scenes().next());
+    for node in scene.iter().flat_map(gltf::Scene::nodes) {
+        load_node(&node, Mat4::IDENTITY, &buffers, &mut mesh)?;
+    }
+    Ok(mesh)
+}
+
+fn load_node(
+    node: &gltf::Node<'_>,
+    parent: Mat4,
+    buffers: &[Vec<u8>],
+    mesh: &mut Mesh,
+) -> Result<()> {
+    let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+    if let Some(node_mesh) = node.mesh() {
+        let name = node_mesh.name().or_else(|| node.name()).unwrap_or_default();
+        for primitive in node_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                warn!("Skipping {:?} primitive of {name}", primitive.mode());
+                continue;
+            }
+            load_primitive(&primitive, name, transform, buffers, mesh)?;
+        }
+    }
+    for child in node.children() {
+        load_node(&child, transform, buffers, mesh)?;
+    }
+    Ok(())
+}
+
+fn load_primitive(
+    primitive: &gltf::Primitive<'_>,
+    name: &str,
+    transform: Mat4,
+    buffers: &[Vec<u8>],
+    mesh: &mut Mesh,
+) -> Result<()> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    let Some(positions) = reader.read_positions() else {
+        warn!("Skipping primitive of {name} without positions");
+        return Ok(());
+    };
+    let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+    let positions: Vec<Vec3> = positions
+        .map(|p| transform.transform_point3(Vec3::from(p)))
+        .collect();
+    let normals: Option<Vec<Vec3>> = reader.read_normals().map(|normals| {
+        normals
+            .map(|n| (normal_matrix * Vec3::from(n)).normalize_or_zero())
+            .collect()
+    });
+    // Mirroring transforms flip the handedness of the tangent frames.
+    let mirror = Mat3::from_mat4(transform).determinant().signum();
+    // Provided tangents must be ignored when normals are missing.
+    let tangents: Option<Vec<Vec4>> =
+        reader
+            .read_tangents()
+            .filter(|_| normals.is_some())
+            .map(|tangents| {
+                tangents
+                    .map(|t| {
+                        let tangent = transform.transform_vector3(Vec4::from(t).truncate());
+                        tangent.normalize_or_zero().extend(t[3] * mirror)
+                    })
+                    .collect()
+            });
+    let uvs: Vec<Vec2> = reader.read_tex_coords(0).map_or_else(
+        || vec![Vec2::ZERO; positions.len()],
+        |uvs| uvs.into_f32().map(Vec2::from).collect(),
+    );
+    let colors: Vec<Vec3> = reader.read_colors(0).map_or_else(
+        || vec![Vec3::ZERO; positions.len()],
+        |colors| colors.into_rgb_f32().map(Vec3::from).collect(),
+    );
+    let mut indices: Vec<u32> = reader.read_indices().map_or_else(
+        || (0..positions.len() as u32).collect(),
+        |indices| indices.into_u32().collect(),
+    );
+    // They also turn the front faces around.
+    if mirror < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    let first_index = mesh.indices.len() as u32;
+    let submesh = Submesh {
+        name: name.to_owned(),
+        indices: first_index..first_index + indices.len() as u32,
+        base_vertex: i32::try_from(mesh.vertices.len())
+            .map_err(|e| Error::Validation(format!("{name} has too many vertices: {e}")))?,
+        material_id: primitive.material().index(),
+    };
+    let first_vertex = mesh.vertices.len();
+    let has_normals = normals.is_some();
+    let normals = normals.unwrap_or_else(|| vec![Vec3::ZERO; positions.len()]);
+    mesh.indices.extend(indices);
+    mesh.vertices
+        .extend(
+            positions
+                .into_iter()
+                .zip(normals)
+                .zip(colors)
+                .zip(uvs)
+                .map(|(((p, n), c), t)| VertexAttribute {
+                    position: p,
+                    tangent: Vec3::X,
+                    handedness: 1.0,
+                    normal: n,
+                    color: c,
+                    uv: t,
+                }),
+        );
+    if !has_normals {
+        generate_normals(
+            &mut mesh.vertices,
+            &mut mesh.indices,
+            std::slice::from_ref(&submesh),
+            NormalGeneration::Flat,
+        )?;
+    }
+    match tangents {
+        Some(tangents) => {
+            for (vertex, tangent) in mesh.vertices[first_vertex..].iter_mut().zip(tangents) {
+                vertex.tangent = tangent.truncate();
+                vertex.handedness = tangent.w;
+            }
+        }
+        None => compute_tangents(
+            &mut mesh.vertices,
+            &mut mesh.indices,
+            std::slice::from_ref(&submesh),
+        )?,
+    }
+    mesh.submeshes.push(submesh);
+    Ok(())
+}
+
+fn material_descriptor(
+    material: &gltf::Material<'_>,
+    buffers: &[Vec<u8>],
+    directory: &Path,
+) -> Result<MaterialDescriptor> {
+    let pbr = material.pbr_metallic_roughness();
+    let texture =
+        |texture: gltf::Texture<'_>| texture_source(&texture.source(), buffers, directory);
+    Ok(MaterialDescriptor {
+        name: material.name().unwrap_or_default().to_owned(),
+        diffuse_texture: pbr
+            .base_color_texture()
+            .map(|info| texture(info.texture()))
+            .transpose()?,
+        normal_texture: material
+            .normal_texture()
+            .map(|normal| texture(normal.texture()))
+            .transpose()?,
+        diffuse: Vec4::from(pbr.base_color_factor()).truncate(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|info| texture(info.texture()))
+            .transpose()?,
+        occlusion_texture: material
+            .occlusion_texture()
+            .map(|occlusion| texture(occlusion.texture()))
+            .transpose()?,
+        occlusion_strength: material
+            .occlusion_texture()
+            .map_or(1.0, |occlusion| occlusion.strength()),
+        emissive: Vec3::from(material.emissive_factor()),
+        emissive_texture: material
+            .emissive_texture()
+            .map(|info| texture(info.texture()))
+            .transpose()?,
+        ..Default::default()
+    })
+}
+
+fn texture_source(
+    image: &gltf::Image<'_>,
+    buffers: &[Vec<u8>],
+    directory: &Path,
+) -> Result<TextureSource> {
+    let name = image
+        .name()
+        .map_or_else(|| format!("glTF image {}", image.index()), str::to_owned);
+    Ok(match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let bytes = buffers
+                .get(view.buffer().index())
+                .and_then(|buffer| buffer.get(view.offset()..view.offset() + view.length()))
+                .ok_or_else(|| Error::Validation(format!("{name} is outside of its buffer")))?;
+            TextureSource::Memory {
+                name,
+                bytes: Arc::from(bytes),
+            }
+        }
+        gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => TextureSource::Memory {
+            name,
+            bytes: Arc::from(read_uri(uri, directory)?),
+        },
+        gltf::image::Source::Uri { uri, .. } => TextureSource::File(uri_path(uri, directory)),
+    })
+}
+
+/// Reads a `data:` URI or a file relative to the glTF file.
+fn read_uri(uri: &str, directory: &Path) -> Result<Vec<u8>> {
+    let Some(data) = uri.strip_prefix("data:") else {
+        let path = uri_path(uri, directory);
+        return std::fs::read(&path).map_err(|e| Error::Io { path, source: e });
+    };
+    let (_, encoded) = data
+        .split_once(";base64,")
+        .ok_or_else(|| Error::Validation("only base64 data URIs are supported".to_owned()))?;
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| Error::Validation(format!("invalid base64 data URI: {e}")))
+}
+
+fn uri_path(uri: &str, directory: &Path) -> PathBuf {
+    // Spaces are the only escape commonly found in exported asset names.
+    directory.join(uri.replace("%20", " "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangle_with_data_uri_material() {
+        let mesh = load_gltf("resources/gltf/triangle.gltf").unwrap();
+
+        assert_eq!(mesh.vertices.len(), 3);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.submeshes.len(), 1);
+        assert_eq!(mesh.submeshes[0].name, "Triangle");
+        assert_eq!(mesh.submeshes[0].material_id, Some(0));
+        // glTF texture coordinates already have their origin at the top left.
+        assert_eq!(mesh.vertices[2].uv, Vec2::new(0.0, 0.0));
+        // No tangents in the file, they are computed from the UVs.
+        assert!(mesh.vertices[0].tangent.abs_diff_eq(Vec3::X, 1e-6));
+
+        let material = &mesh.materials[0];
+        assert_eq!(material.name, "Orange");
+        assert_eq!(material.diffuse, Vec3::splat(0.5));
+        assert!((material.metallic - 0.25).abs() < f32::EPSILON);
+        assert!((material.roughness - 0.75).abs() < f32::EPSILON);
+        assert_eq!(material.emissive, Vec3::new(0.1, 0.2, 0.3));
+        let Some(TextureSource::Memory { name, bytes }) = &material.diffuse_texture else {
+            panic!("Expected an embedded base color texture");
+        };
+        assert_eq!(name, "orange");
+        let image = image::load_from_memory(bytes).unwrap().into_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 128, 0, 255]);
+    }
+
+    #[test]
+    fn node_hierarchy_and_tangents() {
+        let mesh = load_gltf("resources/gltf/hierarchy.gltf").unwrap();
+
+        // Root translates by (0, 0, 2) and scales by 2, the child translates by (1, 0, 0).
+        let positions: Vec<_> = mesh.vertices.iter().map(|v| v.position).collect();
+        assert_eq!(
+            positions[..3],
+            [
+                Vec3::new(2.0, 0.0, 2.0),
+                Vec3::new(4.0, 0.0, 2.0),
+                Vec3::new(2.0, 2.0, 2.0)
+            ]
+        );
+        let vertex = mesh.vertices[0];
+        assert_eq!(vertex.normal, Vec3::Z);
+        assert_eq!(vertex.tangent, Vec3::X);
+        assert!((vertex.handedness - 1.0).abs() < f32::EPSILON);
+        // Non-indexed primitives get sequential indices.
+        assert_eq!(mesh.indices[..3], [0, 1, 2]);
+
+        let Some(TextureSource::Memory { bytes, .. }) = &mesh.materials[0].normal_texture else {
+            panic!("Expected a normal map stored in a buffer view");
+        };
+        let image = image::load_from_memory(bytes).unwrap().into_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [128, 128, 255, 255]);
+    }
+
+    #[test]
+    fn mirrored_nodes_keep_front_faces() {
+        let mesh = load_gltf("resources/gltf/hierarchy.gltf").unwrap();
+        let mirrored = &mesh.submeshes[1];
+        let base = usize::try_from(mirrored.base_vertex).unwrap();
+        let triangle =
+            &mesh.indices[mirrored.indices.start as usize..mirrored.indices.end as usize];
+        assert_eq!(triangle, [0, 2, 1]);
+
+        // Counterclockwise around the normal, like the triangle that is not mirrored.
+        let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[base + triangle[i] as usize]);
+        let face_normal = (b.position - a.position).cross(c.position - a.position);
+        assert!(
+            face_normal.dot(a.normal) > 0.0,
+            "{face_normal} {}",
+            a.normal
+        );
+        assert_eq!(a.tangent, -Vec3::X);
+        assert!((a.handedness + 1.0).abs() < f32::EPSILON);
+    }
+}
@@ -0,0 +1,572 @@
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{Read, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use glam::Vec3;
+use tracing::{info, warn};
+
+use super::{
+    load_geometry, LoadOptions, MaterialDescriptor, Mesh, Submesh, TextureSource, VertexAttribute,
+    VertexAttributeLayout,
+};
+use crate::error::{Error, Result};
+
+const MAGIC: [u8; 8] = *b"WGPUMESH";
+/// Bumped whenever the file layout below changes.
+const VERSION: u32 = 1;
+
+/// Start of a mesh cache, followed by the vertices, the indices and the submeshes and
+/// materials.
+///
+/// The vertices and indices are stored as they are in memory, at offsets aligned for
+/// them, so `read_cache` casts them out of the file contents instead of parsing them.
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct Header {
+    magic: [u8; 8],
+    /// See `source_hash`.
+    source_hash: u64,
+    /// See `vertex_layout_hash`.
+    vertex_layout_hash: u64,
+    version: u32,
+    vertex_count: u32,
+    index_count: u32,
+    metadata_size: u32,
+}
+
+/// Loads an OBJ file like [`load_geometry`], through a cache next to it.
+///
+/// The cache is used when the OBJ, its MTL files, the load options and the layout of
+/// `VertexAttribute` are unchanged. Otherwise the OBJ is parsed and the cache rewritten.
+pub fn load_cached_geometry(path: impl AsRef<Path> + Debug, options: LoadOptions) -> Result<Mesh> {
+    let path = path.as_ref();
+    // Missing sources are reported by `load_geometry`.
+    let Ok(source_hash) = source_hash(path, options) else {
+        return load_geometry(path, options);
+    };
+    let layout_hash = vertex_layout_hash(&VertexAttribute::layout());
+    let cache = cache_path(path);
+    match read_cache(&cache, source_hash, layout_hash) {
+        Ok(mesh) => {
+            info!("Loaded {} from its cache", path.display());
+            return Ok(mesh);
+        }
+        Err(e) => info!("Rebuilding the mesh cache of {}: {e}", path.display()),
+    }
+    let mesh = load_geometry(path, options)?;
+    if let Err(e) = write_cache(&cache, &mesh, source_hash, layout_hash) {
+        warn!("Failed to write the mesh cache of {}: {e}", path.display());
+    }
+    Ok(mesh)
+}
+
+/// `<source>.meshcache`, next to the source.
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut file_name = source.file_name().unwrap_or_default().to_owned();
+    file_name.push(".meshcache");
+    source.with_file_name(file_name)
+}
+
+/// Hash of what a loaded OBJ depends on: its path, its content, the content of the
+/// MTL files it references and the options it is loaded with.
+///
+/// # Errors
+/// If the OBJ cannot be read, missing MTL files are hashed as such.
+pub fn source_hash(path: &Path, options: LoadOptions) -> Result<u64> {
+    let obj = std::fs::read(path).map_err(|e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    let mut hash = Fnv1a::new();
+    // Texture paths are resolved against the directory of the OBJ.
+    hash.write(path.to_string_lossy().as_bytes());
+    hash.write(&obj);
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let libraries = String::from_utf8_lossy(&obj)
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("mtllib "))
+        .flat_map(|names| {
+            names
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    for library in libraries {
+        hash.write(library.as_bytes());
+        match std::fs::read(directory.join(&library)) {
+            Ok(mtl) => hash.write(&mtl),
+            Err(_) => hash.write(b"missing"),
+        }
+    }
+    hash.write(format!("{:?}", options.normals).as_bytes());
+    Ok(hash.finish())
+}
+
+/// Hash of a vertex buffer layout and the byte order it is stored in.
+pub fn vertex_layout_hash(layout: &wgpu::VertexBufferLayout<'_>) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.write(&layout.array_stride.to_le_bytes());
+    hash.write(format!("{:?}", layout.step_mode).as_bytes());
+    for attribute in layout.attributes {
+        hash.write(format!("{:?}", attribute.format).as_bytes());
+        hash.write(&attribute.offset.to_le_bytes());
+        hash.write(&attribute.shader_location.to_le_bytes());
+    }
+    hash.write(if cfg!(target_endian = "little") {
+        b"le"
+    } else {
+        b"be"
+    });
+    hash.finish()
+}
+
+/// Reads the mesh cached at `path`.
+///
+/// The file is read at once into a buffer aligned for `Header`, the header, vertices
+/// and indices are `bytemuck` casts of that buffer. `Mesh` owns its vertices and
+/// indices, so the cast slices are copied into it once.
+///
+/// # Errors
+/// If the file cannot be read, or was written by another version, for another source
+/// or vertex layout.
+pub fn read_cache(path: &Path, source_hash: u64, vertex_layout_hash: u64) -> Result<Mesh> {
+    let io_error = |e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    };
+    let invalid = |reason: &str| Error::Validation(format!("{}: {reason}", path.display()));
+    let mut file = File::open(path).map_err(io_error)?;
+    let len = usize::try_from(file.metadata().map_err(io_error)?.len())
+        .map_err(|e| invalid(&e.to_string()))?;
+    // `u64` words align every section, see `Header`.
+    let mut buffer = vec![0_u64; len.div_ceil(mem::size_of::<u64>())];
+    let bytes = &mut bytemuck::cast_slice_mut::<_, u8>(&mut buffer)[..len];
+    file.read_exact(bytes).map_err(io_error)?;
+    let bytes = &bytes[..];
+
+    let (header, bytes) = bytes
+        .split_at_checked(mem::size_of::<Header>())
+        .ok_or_else(|| invalid("truncated"))?;
+    let header: &Header = bytemuck::from_bytes(header);
+    if header.magic != MAGIC || header.version != VERSION {
+        return Err(invalid(&format!("not a version {VERSION} mesh cache")));
+    }
+    if header.vertex_layout_hash != vertex_layout_hash {
+        return Err(invalid("written for another vertex layout"));
+    }
+    if header.source_hash != source_hash {
+        return Err(invalid("the source changed"));
+    }
+    let size = mem_size::<Header>(1)
+        + mem_size::<VertexAttribute>(header.vertex_count)
+        + mem_size::<u32>(header.index_count)
+        + u64::from(header.metadata_size);
+    if len as u64 != size {
+        return Err(invalid("truncated"));
+    }
+
+    let (vertices, bytes) =
+        bytes.split_at(mem_size::<VertexAttribute>(header.vertex_count) as usize);
+    let (indices, metadata) = bytes.split_at(mem_size::<u32>(header.index_count) as usize);
+    let cast_error = |e: bytemuck::PodCastError| invalid(&format!("{e:?}"));
+    let vertices: &[VertexAttribute] = bytemuck::try_cast_slice(vertices).map_err(cast_error)?;
+    let indices: &[u32] = bytemuck::try_cast_slice(indices).map_err(cast_error)?;
+
+    let mut reader = MetadataReader(metadata);
+    let submeshes = (0..reader.u32()?)
+        .map(|_| reader.submesh())
+        .collect::<Result<Vec<_>>>()?;
+    let materials = (0..reader.u32()?)
+        .map(|_| reader.material())
+        .collect::<Result<Vec<_>>>()?;
+    if submeshes
+        .iter()
+        .any(|submesh| submesh.indices.end > header.index_count)
+    {
+        return Err(invalid("submesh indices out of range"));
+    }
+    Ok(Mesh {
+        vertices: vertices.to_vec(),
+        indices: indices.to_vec(),
+        submeshes,
+        materials,
+    })
+}
+
+/// Writes `mesh` to `path`, through a temporary file so readers never see part of it.
+///
+/// # Errors
+/// If the file cannot be written.
+pub fn write_cache(
+    path: &Path,
+    mesh: &Mesh,
+    source_hash: u64,
+    vertex_layout_hash: u64,
+) -> Result<()> {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    let count = |len: usize| {
+        u32::try_from(len).map_err(|e| Error::Validation(format!("mesh too large to cache: {e}")))
+    };
+    let mut metadata = MetadataWriter::default();
+    metadata.u32(count(mesh.submeshes.len())?);
+    for submesh in &mesh.submeshes {
+        metadata.submesh(submesh);
+    }
+    metadata.u32(count(mesh.materials.len())?);
+    for material in &mesh.materials {
+        metadata.material(material);
+    }
+    let header = Header {
+        magic: MAGIC,
+        source_hash,
+        vertex_layout_hash,
+        version: VERSION,
+        vertex_count: count(mesh.vertices.len())?,
+        index_count: count(mesh.indices.len())?,
+        metadata_size: count(metadata.0.len())?,
+    };
+
+    let temporary = path.with_extension(format!(
+        "meshcache.{}-{}",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let io_error = |e| Error::Io {
+        path: temporary.clone(),
+        source: e,
+    };
+    let write = || -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(File::create(&temporary)?);
+        file.write_all(bytemuck::bytes_of(&header))?;
+        file.write_all(bytemuck::cast_slice(&mesh.vertices))?;
+        file.write_all(bytemuck::cast_slice(&mesh.indices))?;
+        file.write_all(&metadata.0)?;
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(&temporary, path)
+    };
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&temporary);
+        io_error(e)
+    })
+}
+
+fn mem_size<T>(count: u32) -> u64 {
+    std::mem::size_of::<T>() as u64 * u64::from(count)
+}
+
+/// 64-bit FNV-1a, unlike `DefaultHasher` it gives the same hashes in every build.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        // Keeps consecutive writes from running into each other.
+        self.0 = (self.0 ^ bytes.len() as u64).wrapping_mul(0x0100_0000_01b3);
+    }
+
+    const fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Little endian encoding of the submeshes and materials.
+#[derive(Default)]
+struct MetadataWriter(Vec<u8>);
+
+impl MetadataWriter {
+    fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn vec3(&mut self, value: Vec3) {
+        for x in value.to_array() {
+            self.f32(x);
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.0.extend(bytes);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    fn texture(&mut self, texture: Option<&TextureSource>) {
+        match texture {
+            None => self.u32(0),
+            Some(TextureSource::File(path)) => {
+                self.u32(1);
+                self.string(&path.to_string_lossy());
+            }
+            Some(TextureSource::Memory { name, bytes }) => {
+                self.u32(2);
+                self.string(name);
+                self.bytes(bytes);
+            }
+        }
+    }
+
+    fn submesh(&mut self, submesh: &Submesh) {
+        self.string(&submesh.name);
+        self.u32(submesh.indices.start);
+        self.u32(submesh.indices.end);
+        self.0.extend(submesh.base_vertex.to_le_bytes());
+        self.u32(submesh.material_id.map_or(u32::MAX, |id| id as u32));
+    }
+
+    fn material(&mut self, material: &MaterialDescriptor) {
+        self.string(&material.name);
+        self.texture(material.diffuse_texture.as_ref());
+        self.texture(material.normal_texture.as_ref());
+        self.texture(material.specular_texture.as_ref());
+        self.vec3(material.diffuse);
+        self.vec3(material.specular);
+        // NaN stands for `None`, it is not a valid shininess.
+        self.f32(material.shininess.unwrap_or(f32::NAN));
+        self.f32(material.metallic);
+        self.f32(material.roughness);
+        self.texture(material.metallic_roughness_texture.as_ref());
+        self.texture(material.occlusion_texture.as_ref());
+        self.f32(material.occlusion_strength);
+        self.vec3(material.emissive);
+        self.texture(material.emissive_texture.as_ref());
+    }
+}
+
+/// Reads what `MetadataWriter` wrote, in the same order.
+struct MetadataReader<'a>(&'a [u8]);
+
+impl MetadataReader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self.bytes_of_len(N)?;
+        Ok(bytes.try_into().expect("N bytes were taken"))
+    }
+
+    fn bytes_of_len(&mut self, len: usize) -> Result<&[u8]> {
+        if self.0.len() < len {
+            return Err(Error::Validation(
+                "truncated mesh cache metadata".to_owned(),
+            ));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    fn vec3(&mut self) -> Result<Vec3> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn bytes(&mut self) -> Result<&[u8]> {
+        let len = self.u32()? as usize;
+        self.bytes_of_len(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|e| Error::Validation(format!("invalid mesh cache string: {e}")))
+    }
+
+    fn texture(&mut self) -> Result<Option<TextureSource>> {
+        match self.u32()? {
+            0 => Ok(None),
+            1 => Ok(Some(TextureSource::File(self.string()?.into()))),
+            2 => Ok(Some(TextureSource::Memory {
+                name: self.string()?,
+                bytes: self.bytes()?.into(),
+            })),
+            tag => Err(Error::Validation(format!(
+                "invalid mesh cache texture source {tag}"
+            ))),
+        }
+    }
+
+    fn submesh(&mut self) -> Result<Submesh> {
+        Ok(Submesh {
+            name: self.string()?,
+            indices: self.u32()?..self.u32()?,
+            base_vertex: self.take().map(i32::from_le_bytes)?,
+            material_id: Some(self.u32()?)
+                .filter(|&id| id != u32::MAX)
+                .map(|id| id as usize),
+        })
+    }
+
+    fn material(&mut self) -> Result<MaterialDescriptor> {
+        Ok(MaterialDescriptor {
+            name: self.string()?,
+            diffuse_texture: self.texture()?,
+            normal_texture: self.texture()?,
+            specular_texture: self.texture()?,
+            diffuse: self.vec3()?,
+            specular: self.vec3()?,
+            shininess: Some(self.f32()?).filter(|shininess| !shininess.is_nan()),
+            metallic: self.f32()?,
+            roughness: self.f32()?,
+            metallic_roughness_texture: self.texture()?,
+            occlusion_texture: self.texture()?,
+            occlusion_strength: self.f32()?,
+            emissive: self.vec3()?,
+            emissive_texture: self.texture()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::{NormalGeneration, PackedVertex};
+
+    /// A quad and a triangle with a material, written to `<name>.obj` in the temp directory.
+    fn write_obj(name: &str, extra: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join("wgpu-cpp-mesh-cache");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join(format!("{name}.mtl")),
+            "newmtl Painted\nKd 0.5 0.25 1.0\nNs 20\nmap_Kd albedo.jpg\nPm 0.5\n",
+        )
+        .unwrap();
+        let path = directory.join(format!("{name}.obj"));
+        std::fs::write(
+            &path,
+            format!(
+                "mtllib {name}.mtl\no Quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                 vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nusemtl Painted\nf 1/1 2/2 3/3 4/4\n\
+                 o Triangle\nv 0 0 1\nv 1 0 1\nv 0 1 1\nf 5 6 7\n{extra}"
+            ),
+        )
+        .unwrap();
+        let _ = std::fs::remove_file(cache_path(&path));
+        path
+    }
+
+    fn assert_same_mesh(actual: &Mesh, expected: &Mesh) {
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&actual.vertices),
+            bytemuck::cast_slice::<_, u8>(&expected.vertices)
+        );
+        assert_eq!(actual.indices, expected.indices);
+        assert_eq!(actual.submeshes, expected.submeshes);
+        assert_eq!(actual.materials, expected.materials);
+    }
+
+    fn layout_hash() -> u64 {
+        vertex_layout_hash(&VertexAttribute::layout())
+    }
+
+    #[test]
+    fn cached_meshes_match_the_obj() {
+        let path = write_obj("round-trip", "");
+        let options = LoadOptions::default();
+        let parsed = load_geometry(&path, options).unwrap();
+        assert_eq!(cache_path(&path), path.with_extension("obj.meshcache"));
+        assert!(read_cache(&cache_path(&path), 0, 0).is_err());
+
+        let first = load_cached_geometry(&path, options).unwrap();
+        assert_same_mesh(&first, &parsed);
+        let hash = source_hash(&path, options).unwrap();
+        let cached = read_cache(&cache_path(&path), hash, layout_hash()).unwrap();
+        assert_same_mesh(&cached, &parsed);
+        assert_eq!(cached.materials[0].shininess, Some(20.0));
+        assert_same_mesh(&load_cached_geometry(&path, options).unwrap(), &parsed);
+
+        // Fields an OBJ does not set round trip too.
+        let mut edited = parsed;
+        edited.submeshes[1].material_id = None;
+        edited.materials[0].shininess = None;
+        edited.materials[0].normal_texture = Some(TextureSource::Memory {
+            name: "embedded".to_owned(),
+            bytes: [1, 2, 3].into(),
+        });
+        write_cache(&cache_path(&path), &edited, 1, 2).unwrap();
+        assert_same_mesh(&read_cache(&cache_path(&path), 1, 2).unwrap(), &edited);
+    }
+
+    #[test]
+    fn changed_sources_invalidate_the_cache() {
+        let path = write_obj("changed", "");
+        let options = LoadOptions::default();
+        let old = load_cached_geometry(&path, options).unwrap();
+        let cache = cache_path(&path);
+        let old_cache = std::fs::read(&cache).unwrap();
+
+        // One more face, with the cache of the old OBJ.
+        write_obj("changed", "v 2 2 1\nf 5 6 8\n");
+        std::fs::write(&cache, &old_cache).unwrap();
+        let hash = source_hash(&path, options).unwrap();
+        let error = read_cache(&cache, hash, layout_hash()).unwrap_err();
+        assert!(error.to_string().contains("source changed"), "{error}");
+        let mesh = load_cached_geometry(&path, options).unwrap();
+        assert_same_mesh(&mesh, &load_geometry(&path, options).unwrap());
+        assert_eq!(mesh.indices.len(), old.indices.len() + 3);
+        assert!(read_cache(&cache, hash, layout_hash()).is_ok());
+
+        // MTL files and load options count as sources too.
+        std::fs::write(path.with_extension("mtl"), "newmtl Painted\nKd 1 0 0\n").unwrap();
+        let mtl_hash = source_hash(&path, options).unwrap();
+        assert_ne!(mtl_hash, hash);
+        let flat = LoadOptions {
+            normals: NormalGeneration::Flat,
+            ..options
+        };
+        assert_ne!(source_hash(&path, flat).unwrap(), mtl_hash);
+    }
+
+    #[test]
+    fn other_vertex_layouts_invalidate_the_cache() {
+        assert_ne!(vertex_layout_hash(&PackedVertex::layout()), layout_hash());
+
+        let path = write_obj("layout", "");
+        let options = LoadOptions::default();
+        load_cached_geometry(&path, options).unwrap();
+        let hash = source_hash(&path, options).unwrap();
+        let packed_layout = vertex_layout_hash(&PackedVertex::layout());
+        let error = read_cache(&cache_path(&path), hash, packed_layout).unwrap_err();
+        assert!(error.to_string().contains("vertex layout"), "{error}");
+    }
+
+    #[test]
+    fn corrupt_caches_are_rebuilt() {
+        let path = write_obj("corrupt", "");
+        let options = LoadOptions::default();
+        let parsed = load_cached_geometry(&path, options).unwrap();
+        let cache = cache_path(&path);
+        let hash = source_hash(&path, options).unwrap();
+
+        let bytes = std::fs::read(&cache).unwrap();
+        std::fs::write(&cache, &bytes[..bytes.len() - 5]).unwrap();
+        let error = read_cache(&cache, hash, layout_hash()).unwrap_err();
+        assert!(error.to_string().contains("truncated"), "{error}");
+        std::fs::write(&cache, b"not a mesh cache at all, only text").unwrap();
+        assert!(read_cache(&cache, hash, layout_hash()).is_err());
+
+        assert_same_mesh(&load_cached_geometry(&path, options).unwrap(), &parsed);
+        assert_eq!(std::fs::read(&cache).unwrap(), bytes);
+    }
+}
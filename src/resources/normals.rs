@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use super::{Submesh, VertexAttribute};
+use crate::error::{Error, Result};
+
+/// How normals are generated for meshes that do not provide them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalGeneration {
+    /// One normal per face, every edge is hard.
+    Flat,
+    /// Normals averaged over the faces sharing a position.
+    Smooth {
+        weighting: NormalWeighting,
+        /// Edges between faces meeting at a larger angle (in radians) stay hard.
+        crease_angle: f32,
+    },
+}
+
+impl Default for NormalGeneration {
+    fn default() -> Self {
+        Self::Smooth {
+            weighting: NormalWeighting::Angle,
+            crease_angle: 60_f32.to_radians(),
+        }
+    }
+}
+
+/// Contribution of each face to a smooth vertex normal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalWeighting {
+    /// Larger faces contribute more.
+    Area,
+    /// Faces contribute by their angle at the vertex, independent of tessellation.
+    #[default]
+    Angle,
+}
+
+/// Replaces the normals of every submesh by generated ones.
+///
+/// Vertices at the same position are smoothed together even if their UVs
+/// differ. Vertices on a hard edge are split, the copies are appended to
+/// `vertices` and `indices` updated.
+pub fn generate_normals(
+    vertices: &mut Vec<VertexAttribute>,
+    indices: &mut [u32],
+    submeshes: &[Submesh],
+    generation: NormalGeneration,
+) -> Result<()> {
+    for submesh in submeshes {
+        let base_vertex = usize::try_from(submesh.base_vertex).map_err(|e| {
+            Error::Validation(format!("base vertex of submesh {}: {e}", submesh.name))
+        })?;
+        let indices = &mut indices[submesh.indices.start as usize..submesh.indices.end as usize];
+        generate_submesh_normals(vertices, base_vertex, indices, generation)?;
+    }
+    Ok(())
+}
+
+fn generate_submesh_normals(
+    vertices: &mut Vec<VertexAttribute>,
+    base_vertex: usize,
+    indices: &mut [u32],
+    generation: NormalGeneration,
+) -> Result<()> {
+    let position = |index: u32| vertices[base_vertex + index as usize].position;
+    // Not normalized, the length is twice the area of the face.
+    let face_normals: Vec<Vec3> = indices
+        .chunks_exact(3)
+        .map(|face| {
+            let [a, b, c] = [face[0], face[1], face[2]].map(position);
+            (b - a).cross(c - a)
+        })
+        .collect();
+    let corner_angle = |face: usize, corner: usize| {
+        let at = |offset: usize| position(indices[face * 3 + (corner + offset) % 3]);
+        let current = at(0);
+        (at(1) - current)
+            .normalize_or_zero()
+            .dot((at(2) - current).normalize_or_zero())
+            .clamp(-1.0, 1.0)
+            .acos()
+    };
+
+    let mut corners_at: HashMap<[u32; 3], Vec<(usize, usize)>> = HashMap::new();
+    for (corner, &index) in indices.iter().enumerate() {
+        corners_at
+            .entry(position(index).to_array().map(f32::to_bits))
+            .or_default()
+            .push((corner / 3, corner % 3));
+    }
+
+    let corner_normals: Vec<Vec3> = indices
+        .iter()
+        .enumerate()
+        .map(|(corner, &index)| {
+            let face_normal = face_normals[corner / 3].normalize_or_zero();
+            let NormalGeneration::Smooth {
+                weighting,
+                crease_angle,
+            } = generation
+            else {
+                return face_normal;
+            };
+            let min_cos = crease_angle.cos();
+            let smooth: Vec3 = corners_at[&position(index).to_array().map(f32::to_bits)]
+                .iter()
+                .filter(|&&(face, _)| {
+                    // Degenerate faces take the normal of all their neighbours.
+                    face_normal == Vec3::ZERO
+                        || face_normal.dot(face_normals[face].normalize_or_zero()) >= min_cos
+                })
+                .map(|&(face, face_corner)| match weighting {
+                    NormalWeighting::Area => face_normals[face],
+                    NormalWeighting::Angle => {
+                        face_normals[face].normalize_or_zero() * corner_angle(face, face_corner)
+                    }
+                })
+                .sum();
+            let smooth = smooth.normalize_or_zero();
+            if smooth == Vec3::ZERO {
+                face_normal
+            } else {
+                smooth
+            }
+        })
+        .collect();
+
+    // Vertices whose corners got different normals are split, one copy per normal.
+    let mut copies: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (index, normal) in indices.iter_mut().zip(corner_normals) {
+        let same_normal = |copy: &&u32| {
+            vertices[base_vertex + **copy as usize]
+                .normal
+                .abs_diff_eq(normal, 1e-5)
+        };
+        let vertex_copies = copies.entry(*index).or_default();
+        if let Some(&copy) = vertex_copies.iter().find(same_normal) {
+            *index = copy;
+        } else if vertex_copies.is_empty() {
+            vertices[base_vertex + *index as usize].normal = normal;
+            vertex_copies.push(*index);
+        } else {
+            let mut vertex = vertices[base_vertex + *index as usize];
+            vertex.normal = normal;
+            let copy = u32::try_from(vertices.len() - base_vertex)
+                .map_err(|e| Error::Validation(format!("splitting hard edges: {e}")))?;
+            vertices.push(vertex);
+            vertex_copies.push(copy);
+            *index = copy;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::{load_geometry, LoadOptions};
+
+    /// Writes `path` without its `vn` lines and normal indices.
+    fn without_normals(path: &str) -> std::path::PathBuf {
+        let obj = std::fs::read_to_string(path).unwrap();
+        let mut stripped = String::new();
+        for line in obj.lines().filter(|line| !line.starts_with("vn ")) {
+            // `f v/vt/vn` becomes `f v/vt`.
+            let corners: Vec<_> = line
+                .split(' ')
+                .map(|corner| corner.rsplit_once('/').map_or(corner, |(v, _)| v))
+                .collect();
+            stripped.push_str(&corners.join(" "));
+            stripped.push('\n');
+        }
+        let name = std::path::Path::new(path).file_name().unwrap();
+        let output =
+            std::env::temp_dir().join(format!("wgpu-cpp-no-normals-{}", name.to_string_lossy()));
+        std::fs::write(&output, stripped).unwrap();
+        output
+    }
+
+    fn load(path: &str, normals: NormalGeneration) -> crate::resources::Mesh {
+        load_geometry(
+            without_normals(path),
+            LoadOptions {
+                normals,
+                ..LoadOptions::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn plane_gets_up_normals() {
+        for generation in [NormalGeneration::Flat, NormalGeneration::default()] {
+            let mesh = load("resources/plane.obj", generation);
+
+            assert_eq!(mesh.vertices.len(), 4);
+            for v in &mesh.vertices {
+                assert!(v.normal.abs_diff_eq(Vec3::Y, 1e-6), "{v:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn cube_crease_keeps_hard_edges() {
+        for generation in [NormalGeneration::Flat, NormalGeneration::default()] {
+            let mesh = load("resources/cube.obj", generation);
+
+            // Four vertices per face, each with an axis aligned normal.
+            assert_eq!(mesh.vertices.len(), 24);
+            for v in &mesh.vertices {
+                assert!(v.normal.dot(v.position) > 0.0, "{v:?}");
+                assert!((v.normal.abs().max_element() - 1.0).abs() < 1e-6, "{v:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_cube_normals_point_out_of_corners() {
+        for weighting in [NormalWeighting::Area, NormalWeighting::Angle] {
+            let mesh = load(
+                "resources/cube.obj",
+                NormalGeneration::Smooth {
+                    weighting,
+                    crease_angle: std::f32::consts::PI,
+                },
+            );
+
+            for v in &mesh.vertices {
+                // Triangulation makes the area weights uneven, the angle weights are exact.
+                assert!(v.normal.dot(v.position.normalize()) > 0.9, "{v:?}");
+                if weighting == NormalWeighting::Angle {
+                    assert!(v.normal.abs_diff_eq(v.position.normalize(), 1e-5), "{v:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn negative_base_vertices_are_reported() {
+        let mut mesh = load("resources/plane.obj", NormalGeneration::Flat);
+        mesh.submeshes[0].base_vertex = -1;
+        let error = generate_normals(
+            &mut mesh.vertices,
+            &mut mesh.indices,
+            &mesh.submeshes,
+            NormalGeneration::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::Validation(_)), "{error}");
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+
+use glam::Vec3;
+use tracing::info;
+
+use super::{Mesh, VertexAttribute};
+use crate::error::{Error, Result};
+
+/// Size of the FIFO cache `VertexCacheStats` are simulated with.
+const STATS_CACHE_SIZE: usize = 16;
+/// Size of the LRU cache triangles are ordered for, larger than most hardware caches.
+const OPTIMIZER_CACHE_SIZE: usize = 32;
+/// Clusters are split once their ACMR gets this close to the one of the whole cluster,
+/// higher values trade cache efficiency for less overdraw.
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// Post-transform vertex cache efficiency of an index buffer, simulated with a FIFO
+/// cache of 16 vertices that is emptied between submeshes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexCacheStats {
+    /// Average cache misses per triangle, from 0.5 for an ideal grid to 3.
+    pub acmr: f32,
+    /// Average cache misses per vertex, 1 when every vertex is transformed once.
+    pub atvr: f32,
+}
+
+pub fn vertex_cache_stats(mesh: &Mesh) -> VertexCacheStats {
+    let mut misses = 0;
+    for submesh in &mesh.submeshes {
+        let mut cache = FifoCache::new(STATS_CACHE_SIZE);
+        misses += mesh.indices[submesh.indices.start as usize..submesh.indices.end as usize]
+            .iter()
+            .filter(|&&index| cache.access(index))
+            .count();
+    }
+    let triangles: u32 = mesh
+        .submeshes
+        .iter()
+        .map(|s| s.indices.len() as u32 / 3)
+        .sum();
+    VertexCacheStats {
+        acmr: misses as f32 / triangles.max(1) as f32,
+        atvr: misses as f32 / mesh.vertices.len().max(1) as f32,
+    }
+}
+
+/// Prepares `mesh` for drawing, submesh by submesh:
+///
+/// 1. Welds identical vertices.
+/// 2. Orders triangles for the post-transform vertex cache, following Forsyth's
+///    "Linear-Speed Vertex Cache Optimisation".
+/// 3. Orders clusters of triangles facing away from the center of the mesh first, so
+///    more of the triangles behind them fail the depth test.
+/// 4. Orders vertices by first use, for locality of the vertex fetches.
+///
+/// Every submesh gets its own range of vertices starting at `base_vertex`, vertices
+/// that no submesh uses are dropped.
+pub fn optimize_mesh(mesh: &mut Mesh) -> Result<()> {
+    let before = vertex_cache_stats(mesh);
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    for submesh in &mut mesh.submeshes {
+        let base_vertex = usize::try_from(submesh.base_vertex).map_err(|e| {
+            Error::Validation(format!("base vertex of submesh {}: {e}", submesh.name))
+        })?;
+        let (submesh_vertices, mut submesh_indices) = weld_vertices(
+            &mesh.vertices[base_vertex..],
+            &mesh.indices[submesh.indices.start as usize..submesh.indices.end as usize],
+        )?;
+        let clusters = optimize_vertex_cache(&mut submesh_indices, submesh_vertices.len());
+        optimize_overdraw(&mut submesh_indices, &clusters, &submesh_vertices);
+        let submesh_vertices = optimize_vertex_fetch(&mut submesh_indices, &submesh_vertices)?;
+
+        let first_index = to_u32(indices.len())?;
+        submesh.indices = first_index..to_u32(indices.len() + submesh_indices.len())?;
+        submesh.base_vertex = i32::try_from(vertices.len())
+            .map_err(|e| Error::Validation(format!("too many vertices to optimize: {e}")))?;
+        vertices.extend(submesh_vertices);
+        indices.extend(submesh_indices);
+    }
+    let vertex_count = mesh.vertices.len();
+    mesh.vertices = vertices;
+    mesh.indices = indices;
+    let after = vertex_cache_stats(mesh);
+    info!(
+        "Optimized mesh: {vertex_count} -> {} vertices, ACMR {:.3} -> {:.3}, ATVR {:.3} -> {:.3}",
+        mesh.vertices.len(),
+        before.acmr,
+        after.acmr,
+        before.atvr,
+        after.atvr,
+    );
+    Ok(())
+}
+
+/// `value` as an index or vertex number, meshes with more do not fit their buffers.
+fn to_u32(value: usize) -> Result<u32> {
+    u32::try_from(value)
+        .map_err(|e| Error::Validation(format!("too many vertices to optimize: {e}")))
+}
+
+/// The vertices `indices` use, without duplicates, and the indices into them.
+fn weld_vertices(
+    vertices: &[VertexAttribute],
+    indices: &[u32],
+) -> Result<(Vec<VertexAttribute>, Vec<u32>)> {
+    let mut welded = vec![];
+    // Bitwise equality, `-0.0` and `0.0` stay different.
+    let mut welded_indices: HashMap<[u32; 15], u32> = HashMap::new();
+    let indices = indices
+        .iter()
+        .map(|&index| {
+            let vertex = vertices[index as usize];
+            match welded_indices.entry(bytemuck::cast(vertex)) {
+                Entry::Occupied(entry) => Ok(*entry.get()),
+                Entry::Vacant(entry) => {
+                    let welded_index = to_u32(welded.len())?;
+                    welded.push(vertex);
+                    Ok(*entry.insert(welded_index))
+                }
+            }
+        })
+        .collect::<Result<_>>()?;
+    Ok((welded, indices))
+}
+
+/// Score of a vertex in Forsyth's algorithm, by its position in the LRU cache and the
+/// number of triangles left using it.
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    const LAST_TRIANGLE_SCORE: f32 = 0.75;
+    const CACHE_DECAY_POWER: f32 = 1.5;
+    const VALENCE_BOOST_SCALE: f32 = 2.0;
+    const VALENCE_BOOST_POWER: f32 = 0.5;
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The vertices of the last triangle get a fixed score, so the next triangle
+        // does not depend on their order.
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => (1.0 - (position - 3) as f32 / (OPTIMIZER_CACHE_SIZE - 3) as f32)
+            .powf(CACHE_DECAY_POWER),
+    };
+    // Vertices with few triangles left are finished first.
+    VALENCE_BOOST_SCALE.mul_add(
+        (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER),
+        cache_score,
+    )
+}
+
+/// Reorders the triangles of `indices` to reuse transformed vertices, returns the
+/// first triangle of every run that continues from the cache.
+fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) -> Vec<usize> {
+    let triangle_count = indices.len() / 3;
+    let mut triangles_of = vec![vec![]; vertex_count];
+    for (corner, &index) in indices.iter().enumerate() {
+        triangles_of[index as usize].push(corner / 3);
+    }
+    let mut cache_position = vec![None; vertex_count];
+    let mut score: Vec<f32> = triangles_of
+        .iter()
+        .map(|triangles| vertex_score(None, triangles.len()))
+        .collect();
+    let triangle_score = |triangle: usize, scores: &[f32]| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .map(|&index| scores[index as usize])
+            .sum()
+    };
+
+    let mut emitted = vec![false; triangle_count];
+    let mut output = Vec::with_capacity(indices.len());
+    let mut cache: Vec<u32> = Vec::with_capacity(OPTIMIZER_CACHE_SIZE + 3);
+    let mut runs = vec![];
+    // Dead ends continue with the next triangle in the original order.
+    let mut next_unemitted = 0;
+    let mut best = None;
+    for _ in 0..triangle_count {
+        let triangle = best.unwrap_or_else(|| {
+            while emitted[next_unemitted] {
+                next_unemitted += 1;
+            }
+            runs.push(output.len() / 3);
+            next_unemitted
+        });
+        emitted[triangle] = true;
+        let corners = [0, 1, 2].map(|corner| indices[triangle * 3 + corner]);
+        output.extend(corners);
+
+        let mut new_cache = Vec::with_capacity(OPTIMIZER_CACHE_SIZE + 3);
+        for index in corners {
+            if !new_cache.contains(&index) {
+                new_cache.push(index);
+            }
+            triangles_of[index as usize].retain(|&t| t != triangle);
+        }
+        new_cache.extend(cache.iter().filter(|index| !corners.contains(index)));
+        // Vertices pushed out of the cache are rescored once more.
+        for (position, &index) in new_cache.iter().enumerate() {
+            let index = index as usize;
+            cache_position[index] = (position < OPTIMIZER_CACHE_SIZE).then_some(position);
+            score[index] = vertex_score(cache_position[index], triangles_of[index].len());
+        }
+        new_cache.truncate(OPTIMIZER_CACHE_SIZE);
+        cache = new_cache;
+
+        best = cache
+            .iter()
+            .flat_map(|&index| &triangles_of[index as usize])
+            .map(|&candidate| (candidate, triangle_score(candidate, &score)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(candidate, _)| candidate);
+    }
+    indices.copy_from_slice(&output);
+    runs
+}
+
+/// Splits the runs of `optimize_vertex_cache` into clusters that keep most of its cache
+/// efficiency and orders the clusters by how much they face away from the mesh center.
+fn optimize_overdraw(indices: &mut [u32], runs: &[usize], vertices: &[VertexAttribute]) {
+    let triangle_count = indices.len() / 3;
+    let mut clusters = vec![];
+    for (run, &start) in runs.iter().enumerate() {
+        let end = runs.get(run + 1).copied().unwrap_or(triangle_count);
+        let run_misses = cache_misses(&indices[start * 3..end * 3]);
+        let threshold = OVERDRAW_THRESHOLD * run_misses as f32 / (end - start) as f32;
+        clusters.push(start);
+        let mut cache = FifoCache::new(STATS_CACHE_SIZE);
+        let (mut misses, mut triangles) = (0, 0);
+        for triangle in start..end {
+            misses += indices[triangle * 3..triangle * 3 + 3]
+                .iter()
+                .filter(|&&index| cache.access(index))
+                .count();
+            triangles += 1;
+            if triangle + 1 < end && misses as f32 / triangles as f32 <= threshold {
+                clusters.push(triangle + 1);
+                cache = FifoCache::new(STATS_CACHE_SIZE);
+                (misses, triangles) = (0, 0);
+            }
+        }
+    }
+
+    let position = |index: u32| vertices[index as usize].position;
+    // Area weighted normal and centroid of a range of triangles.
+    let normal_and_centroid = |triangles: &[u32]| {
+        let mut normal = Vec3::ZERO;
+        let mut centroid = Vec3::ZERO;
+        let mut area = 0.0;
+        for face in triangles.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]].map(position);
+            let face_normal = (b - a).cross(c - a);
+            normal += face_normal;
+            centroid += (a + b + c) / 3.0 * face_normal.length();
+            area += face_normal.length();
+        }
+        (normal, centroid / f32::max(area, f32::MIN_POSITIVE))
+    };
+    let (_, mesh_centroid) = normal_and_centroid(indices);
+    let mut sorted: Vec<(f32, &[u32])> = clusters
+        .iter()
+        .enumerate()
+        .map(|(cluster, &start)| {
+            let end = clusters.get(cluster + 1).copied().unwrap_or(triangle_count);
+            let triangles = &indices[start * 3..end * 3];
+            let (normal, centroid) = normal_and_centroid(triangles);
+            let facing = (centroid - mesh_centroid).dot(normal.normalize_or_zero());
+            (facing, triangles)
+        })
+        .collect();
+    sorted.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    let ordered: Vec<u32> = sorted.into_iter().flat_map(|(_, t)| t).copied().collect();
+    indices.copy_from_slice(&ordered);
+}
+
+/// Renumbers the vertices in the order `indices` first uses them and returns them in
+/// that order.
+fn optimize_vertex_fetch(
+    indices: &mut [u32],
+    vertices: &[VertexAttribute],
+) -> Result<Vec<VertexAttribute>> {
+    let mut remap = vec![None; vertices.len()];
+    let mut ordered = Vec::with_capacity(vertices.len());
+    for index in indices {
+        let remapped = &mut remap[*index as usize];
+        *index = if let Some(remapped) = *remapped {
+            remapped
+        } else {
+            let new_index = to_u32(ordered.len())?;
+            ordered.push(vertices[*index as usize]);
+            *remapped.insert(new_index)
+        };
+    }
+    Ok(ordered)
+}
+
+fn cache_misses(indices: &[u32]) -> usize {
+    let mut cache = FifoCache::new(STATS_CACHE_SIZE);
+    indices.iter().filter(|&&index| cache.access(index)).count()
+}
+
+/// Post-transform vertex cache of GPUs without an LRU policy.
+struct FifoCache {
+    entries: VecDeque<u32>,
+    size: usize,
+}
+
+impl FifoCache {
+    fn new(size: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(size),
+            size,
+        }
+    }
+
+    /// Whether `index` missed the cache, it is then added.
+    fn access(&mut self, index: u32) -> bool {
+        if self.entries.contains(&index) {
+            return false;
+        }
+        if self.entries.len() == self.size {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(index);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::*;
+    use crate::resources::Submesh;
+
+    /// A `size` x `size` quad grid, its triangles in the order `order` gives them.
+    fn grid(size: u32, order: impl Fn(usize) -> usize) -> Mesh {
+        let vertex = |x: u32, y: u32| VertexAttribute {
+            position: Vec3::new(x as f32, y as f32, 0.0),
+            tangent: Vec3::X,
+            handedness: 1.0,
+            normal: Vec3::Z,
+            color: Vec3::ONE,
+            uv: Vec2::new(x as f32, y as f32) / size as f32,
+        };
+        let vertices = (0..=size)
+            .flat_map(|y| (0..=size).map(move |x| vertex(x, y)))
+            .collect();
+        let index = |x: u32, y: u32| y * (size + 1) + x;
+        let triangles: Vec<[u32; 3]> = (0..size)
+            .flat_map(|y| (0..size).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                [
+                    [index(x, y), index(x + 1, y), index(x, y + 1)],
+                    [index(x + 1, y), index(x + 1, y + 1), index(x, y + 1)],
+                ]
+            })
+            .collect();
+        let indices: Vec<u32> = (0..triangles.len())
+            .flat_map(|triangle| triangles[order(triangle)])
+            .collect();
+        Mesh {
+            submeshes: vec![Submesh {
+                name: "Grid".to_owned(),
+                indices: 0..indices.len() as u32,
+                base_vertex: 0,
+                material_id: None,
+            }],
+            vertices,
+            indices,
+            materials: vec![],
+        }
+    }
+
+    /// Triangles by the positions of their corners, starting at the smallest index.
+    fn triangles(mesh: &Mesh) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<_> = mesh
+            .submeshes
+            .iter()
+            .flat_map(|submesh| {
+                let base_vertex = usize::try_from(submesh.base_vertex).unwrap();
+                mesh.indices[submesh.indices.start as usize..submesh.indices.end as usize]
+                    .chunks_exact(3)
+                    .map(move |face| {
+                        [0, 1, 2].map(|corner| {
+                            let vertex = mesh.vertices[base_vertex + face[corner] as usize];
+                            vertex.position.to_array().map(f32::to_bits)
+                        })
+                    })
+            })
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn optimization_improves_the_vertex_cache() {
+        // 32 x 32 quads, in scanline order and shuffled.
+        let triangle_count = 2048;
+        for (mesh, max_before) in [
+            (grid(32, |t| t), 1.1),
+            (grid(32, |t| t * 789 % triangle_count), 3.0),
+        ] {
+            let before = vertex_cache_stats(&mesh);
+            let mut optimized = mesh.clone();
+            optimize_mesh(&mut optimized).unwrap();
+            let after = vertex_cache_stats(&optimized);
+
+            assert!(before.acmr > 0.9 && before.acmr <= max_before, "{before:?}");
+            assert!(after.acmr < 0.8, "{before:?} -> {after:?}");
+            assert!(after.atvr < before.atvr, "{before:?} -> {after:?}");
+            assert_eq!(optimized.vertices.len(), 33 * 33);
+        }
+    }
+
+    #[test]
+    fn optimization_keeps_the_triangles() {
+        let mut mesh = grid(8, |t| t * 37 % 128);
+        // The second half of the triangles in their own submesh.
+        mesh.submeshes.push(Submesh {
+            name: "Second".to_owned(),
+            indices: 192..384,
+            base_vertex: 0,
+            material_id: None,
+        });
+        mesh.submeshes[0].indices = 0..192;
+        let mut optimized = mesh.clone();
+        optimize_mesh(&mut optimized).unwrap();
+
+        assert_eq!(triangles(&optimized), triangles(&mesh));
+        // Each submesh keeps the vertices it uses, in the order it first uses them.
+        let second = &optimized.submeshes[1];
+        assert!(second.base_vertex > 0);
+        let mut next = 0;
+        for &index in &optimized.indices[second.indices.start as usize..] {
+            assert!(index <= next);
+            next = next.max(index + 1);
+        }
+        assert_eq!(
+            optimized.vertices.len(),
+            usize::try_from(second.base_vertex).unwrap() + next as usize
+        );
+    }
+
+    #[test]
+    fn identical_vertices_are_welded() {
+        let mut mesh = grid(1, |t| t);
+        // Both triangles get their own copies of the shared corners.
+        let shared = [mesh.vertices[1], mesh.vertices[2]];
+        mesh.vertices.extend(shared);
+        mesh.indices[3..6].copy_from_slice(&[4, 3, 5]);
+        // Same position, different normal.
+        mesh.vertices.push(VertexAttribute {
+            normal: Vec3::NEG_Z,
+            ..mesh.vertices[0]
+        });
+        mesh.indices.extend([6, 4, 5]);
+        mesh.submeshes[0].indices = 0..9;
+        optimize_mesh(&mut mesh).unwrap();
+
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.indices.len(), 9);
+    }
+
+    #[test]
+    fn negative_base_vertices_are_reported() {
+        let mut mesh = grid(2, |t| t);
+        mesh.submeshes[0].base_vertex = -1;
+        let error = optimize_mesh(&mut mesh).unwrap_err();
+        assert!(matches!(error, Error::Validation(_)), "{error}");
+    }
+}
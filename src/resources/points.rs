@@ -0,0 +1,181 @@
+use std::{fmt::Display, path::Path};
+
+use glam::{Vec2, Vec3};
+
+use super::VertexAttribute;
+use crate::error::{Error, Result};
+
+/// A syntax error in a `[points]` file, `line` starts at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointsError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for PointsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for PointsError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Points,
+    Indices,
+}
+
+/// Loads a geometry file in the sectioned text format of the tutorial.
+///
+/// See [`parse_points`] for the format.
+pub fn load_points(path: impl AsRef<Path>) -> Result<(Vec<VertexAttribute>, Vec<u32>)> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    parse_points(&text).map_err(|e| Error::Points {
+        path: path.to_owned(),
+        source: e,
+    })
+}
+
+/// Parses the `[points]` and `[indices]` sections of a geometry file.
+///
+/// Points are either 2D, `x y r g b`, or 3D with a normal,
+/// `x y z nx ny nz r g b`, the first point decides for the whole file. 2D points
+/// lie in the z = 0 plane and face +Z. Indices are listed as triangles. Lines
+/// starting with `#` and empty lines are ignored.
+pub fn parse_points(text: &str) -> Result<(Vec<VertexAttribute>, Vec<u32>), PointsError> {
+    let mut section = None;
+    let mut columns = None;
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut last_line = 0;
+    for (line, content) in (1..).zip(text.lines()) {
+        last_line = line;
+        let error = |message: String| PointsError { line, message };
+        let content = content.trim();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        if content.starts_with('[') {
+            section = match content {
+                "[points]" => Some(Section::Points),
+                "[indices]" => Some(Section::Indices),
+                _ => return Err(error(format!("unknown section {content}"))),
+            };
+            continue;
+        }
+        match section {
+            None => return Err(error("expected [points] or [indices] first".to_owned())),
+            Some(Section::Points) => {
+                let values = content
+                    .split_whitespace()
+                    .map(|value| {
+                        value
+                            .parse::<f32>()
+                            .map_err(|e| error(format!("invalid number {value:?}: {e}")))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let expected = *columns.get_or_insert(values.len());
+                if values.len() != expected {
+                    return Err(error(format!(
+                        "expected {expected} values like the first point, found {}",
+                        values.len()
+                    )));
+                }
+                let (position, normal, color) = match values.len() {
+                    5 => (
+                        Vec2::from_slice(&values).extend(0.0),
+                        Vec3::Z,
+                        Vec3::from_slice(&values[2..]),
+                    ),
+                    9 => (
+                        Vec3::from_slice(&values),
+                        Vec3::from_slice(&values[3..]),
+                        Vec3::from_slice(&values[6..]),
+                    ),
+                    count => {
+                        return Err(error(format!(
+                        "expected 5 (x y r g b) or 9 (x y z nx ny nz r g b) values, found {count}"
+                    )))
+                    }
+                };
+                vertices.push(VertexAttribute {
+                    position,
+                    tangent: Vec3::X,
+                    handedness: 1.0,
+                    normal,
+                    color,
+                    uv: Vec2::ZERO,
+                });
+            }
+            Some(Section::Indices) => {
+                for value in content.split_whitespace() {
+                    let index = value
+                        .parse::<u32>()
+                        .map_err(|e| error(format!("invalid index {value:?}: {e}")))?;
+                    if index as usize >= vertices.len() {
+                        return Err(error(format!(
+                            "index {index} out of range, there are {} points",
+                            vertices.len()
+                        )));
+                    }
+                    indices.push(index);
+                }
+            }
+        }
+    }
+    if !indices.len().is_multiple_of(3) {
+        return Err(PointsError {
+            line: last_line,
+            message: format!("{} indices do not form triangles", indices.len()),
+        });
+    }
+    Ok((vertices, indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webgpu_logo_is_2d() {
+        let (vertices, indices) = load_points("resources/webgpu.txt").unwrap();
+
+        assert_eq!(vertices.len(), 15);
+        assert_eq!(indices.len(), 15);
+        assert_eq!(vertices[0].position, Vec3::new(0.5, 0.0, 0.0));
+        assert_eq!(vertices[0].color, Vec3::new(0.0, 0.353, 0.612));
+        assert!(vertices.iter().all(|v| v.normal == Vec3::Z));
+    }
+
+    #[test]
+    fn pyramid_has_normals() {
+        let (vertices, indices) = load_points("resources/pyramid.txt").unwrap();
+
+        assert_eq!(vertices.len(), 16);
+        assert_eq!(indices.len(), 18);
+        assert_eq!(indices[..6], [0, 1, 2, 0, 2, 3]);
+        assert_eq!(vertices[4].position, Vec3::new(-0.5, -0.5, -0.3));
+        assert_eq!(vertices[4].normal, Vec3::new(0.0, -0.848, 0.53));
+        assert_eq!(vertices[15].color, Vec3::ONE);
+    }
+
+    #[test]
+    fn errors_report_line_numbers() {
+        let error = |text| parse_points(text).unwrap_err();
+
+        assert_eq!(error("0 0 1 1 1\n").line, 1);
+        assert_eq!(error("[points]\n# x y r g b\n0 0 1 1 1\n0 0 1 1\n").line, 4);
+        assert_eq!(error("[points]\n0 0 1 x 1\n").line, 2);
+        assert_eq!(error("[points]\n0 0 1 1 1\n[indices]\n0 0 1\n").line, 4);
+        assert_eq!(error("[points]\n0 0 1 1 1\n[indices]\n0 0\n\n").line, 5);
+        assert_eq!(
+            error("[lines]\n").to_string(),
+            "line 1: unknown section [lines]"
+        );
+    }
+}
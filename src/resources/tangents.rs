@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+
+use glam::{Vec2, Vec3};
+
+use super::{Submesh, VertexAttribute};
+use crate::error::{Error, Result};
+
+/// Faces with a smaller (doubled) UV area do not define a tangent direction.
+const DEGENERATE_UV_AREA: f32 = 1e-12;
+
+/// Tangent of a face along increasing U, and whether its UV mapping is mirrored.
+struct FaceTangent {
+    tangent: Vec3,
+    orientation_preserving: bool,
+}
+
+/// Computes per-vertex tangents following the `MikkTSpace` conventions.
+///
+/// Every face contributes its tangent projected onto the vertex normal and
+/// weighted by the corner angle. Vertices shared by faces with mirrored UVs are
+/// split, the copies are appended to `vertices` and `indices` updated, so each
+/// vertex has a single `handedness` with
+/// `bitangent = handedness * cross(normal, tangent)`.
+///
+/// The copies go to the end of `vertices`, after the vertices of every submesh,
+/// and are indexed relative to the `base_vertex` of the submesh they belong to.
+/// The vertices of a submesh are therefore only contiguous when it is the last one.
+///
+/// UVs are stored with V pointing down, they are flipped back to the V up
+/// convention normal maps are baked with.
+pub fn compute_tangents(
+    vertices: &mut Vec<VertexAttribute>,
+    indices: &mut [u32],
+    submeshes: &[Submesh],
+) -> Result<()> {
+    for submesh in submeshes {
+        let base_vertex = usize::try_from(submesh.base_vertex).map_err(|e| {
+            Error::Validation(format!("base vertex of submesh {}: {e}", submesh.name))
+        })?;
+        let indices = &mut indices[submesh.indices.start as usize..submesh.indices.end as usize];
+        compute_submesh_tangents(vertices, base_vertex, indices)?;
+    }
+    Ok(())
+}
+
+fn compute_submesh_tangents(
+    vertices: &mut Vec<VertexAttribute>,
+    base_vertex: usize,
+    indices: &mut [u32],
+) -> Result<()> {
+    let vertex = |index: u32| base_vertex + index as usize;
+    let faces: Vec<Option<FaceTangent>> = indices
+        .chunks_exact(3)
+        .map(|face| face_tangent([0, 1, 2].map(|corner| &vertices[vertex(face[corner])])))
+        .collect();
+
+    // Vertices only used by degenerate faces keep an arbitrary tangent.
+    for &index in indices.iter() {
+        let v = &mut vertices[vertex(index)];
+        v.tangent = fallback_tangent(v.normal);
+        v.handedness = 1.0;
+    }
+
+    let mut accumulated: BTreeMap<(u32, bool), Vec3> = BTreeMap::new();
+    for (face, face_tangent) in indices.chunks_exact(3).zip(&faces) {
+        let Some(face_tangent) = face_tangent else {
+            continue;
+        };
+        for corner in 0..3 {
+            let current = &vertices[vertex(face[corner])];
+            let next = &vertices[vertex(face[(corner + 1) % 3])];
+            let previous = &vertices[vertex(face[(corner + 2) % 3])];
+            let normal = current.normal.normalize_or_zero();
+            let project = |v: Vec3| (v - normal * normal.dot(v)).normalize_or_zero();
+            let angle = project(next.position - current.position)
+                .dot(project(previous.position - current.position))
+                .clamp(-1.0, 1.0)
+                .acos();
+            *accumulated
+                .entry((face[corner], face_tangent.orientation_preserving))
+                .or_default() += project(face_tangent.tangent) * angle;
+        }
+    }
+
+    // Split vertices used with both handedness, mirrored faces get the copy.
+    let mut mirrored_copies = BTreeMap::new();
+    for &(index, orientation_preserving) in accumulated.keys() {
+        if !orientation_preserving && accumulated.contains_key(&(index, true)) {
+            let copy = u32::try_from(vertices.len() - base_vertex)
+                .map_err(|e| Error::Validation(format!("splitting mirrored UVs: {e}")))?;
+            vertices.push(vertices[vertex(index)]);
+            mirrored_copies.insert(index, copy);
+        }
+    }
+    for (face, face_tangent) in indices.chunks_exact_mut(3).zip(&faces) {
+        if face_tangent
+            .as_ref()
+            .is_some_and(|face_tangent| !face_tangent.orientation_preserving)
+        {
+            for index in face {
+                *index = mirrored_copies.get(index).copied().unwrap_or(*index);
+            }
+        }
+    }
+
+    for (&(index, orientation_preserving), &tangent) in &accumulated {
+        let index = if orientation_preserving {
+            index
+        } else {
+            mirrored_copies.get(&index).copied().unwrap_or(index)
+        };
+        let v = &mut vertices[vertex(index)];
+        let tangent = tangent.normalize_or_zero();
+        if tangent != Vec3::ZERO {
+            v.tangent = tangent;
+        }
+        v.handedness = if orientation_preserving { 1.0 } else { -1.0 };
+    }
+    Ok(())
+}
+
+fn face_tangent(corners: [&VertexAttribute; 3]) -> Option<FaceTangent> {
+    let flip = |uv: Vec2| Vec2::new(uv.x, -uv.y);
+    let d1 = corners[1].position - corners[0].position;
+    let d2 = corners[2].position - corners[0].position;
+    let t1 = flip(corners[1].uv - corners[0].uv);
+    let t2 = flip(corners[2].uv - corners[0].uv);
+
+    let signed_area = t1.perp_dot(t2);
+    let tangent = (d1 * t2.y - d2 * t1.y) * signed_area.signum();
+    if signed_area.abs() < DEGENERATE_UV_AREA || !tangent.is_finite() {
+        return None;
+    }
+    Some(FaceTangent {
+        tangent: tangent.normalize_or_zero(),
+        orientation_preserving: signed_area > 0.0,
+    })
+}
+
+fn fallback_tangent(normal: Vec3) -> Vec3 {
+    let normal = normal.normalize_or_zero();
+    if normal == Vec3::ZERO {
+        Vec3::X
+    } else {
+        normal.any_orthonormal_vector()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::{load_geometry, LoadOptions};
+
+    fn bitangent(v: &VertexAttribute) -> Vec3 {
+        v.normal.cross(v.tangent) * v.handedness
+    }
+
+    /// Checks every vertex has a unit tangent orthogonal to its normal and that
+    /// the frame follows the UV directions of every face using it.
+    fn assert_frames_follow_uvs(vertices: &[VertexAttribute], indices: &[u32]) {
+        for v in vertices {
+            assert!(v.tangent.is_normalized(), "{v:?}");
+            assert!(v.tangent.dot(v.normal).abs() < 1e-5, "{v:?}");
+            assert!((v.handedness.abs() - 1.0).abs() < f32::EPSILON, "{v:?}");
+        }
+        for face in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| vertices[face[i] as usize]);
+            let (d1, d2) = (b.position - a.position, c.position - a.position);
+            let (t1, t2) = (b.uv - a.uv, c.uv - a.uv);
+            let determinant = t1.perp_dot(t2);
+            let along_u = (d1 * t2.y - d2 * t1.y) / determinant;
+            // UVs point down, bitangents point towards decreasing V.
+            let along_v = (d1 * t2.x - d2 * t1.x) / determinant;
+            for v in [a, b, c] {
+                assert!(v.tangent.dot(along_u.normalize()) > 0.99, "{v:?}");
+                assert!(bitangent(&v).dot(along_v.normalize()) > 0.99, "{v:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn plane_tangents_follow_u() {
+        let mesh = load_geometry("resources/plane.obj", LoadOptions::default()).unwrap();
+
+        assert_eq!(mesh.vertices.len(), 4);
+        for v in &mesh.vertices {
+            assert_eq!(v.tangent, Vec3::X);
+            assert!((v.handedness - 1.0).abs() < f32::EPSILON);
+            assert_eq!(bitangent(v), Vec3::NEG_Z);
+        }
+        assert_frames_follow_uvs(&mesh.vertices, &mesh.indices);
+    }
+
+    #[test]
+    fn cube_faces_have_orthonormal_frames() {
+        let mesh = load_geometry("resources/cube.obj", LoadOptions::default()).unwrap();
+
+        // Every face has its own normal, so no vertex needs to be split.
+        assert_eq!(mesh.vertices.len(), 24);
+        assert_frames_follow_uvs(&mesh.vertices, &mesh.indices);
+    }
+
+    #[test]
+    fn mirrored_uvs_split_shared_vertices() {
+        let path = std::env::temp_dir().join("wgpu-cpp-mirrored-uvs.obj");
+        std::fs::write(
+            &path,
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv -1 0 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n\
+             f 1/1/1 2/2/1 3/3/1\nf 1/1/1 3/3/1 4/2/1\n",
+        )
+        .unwrap();
+        let mesh = load_geometry(&path, LoadOptions::default()).unwrap();
+
+        // The two vertices on the mirror edge are duplicated.
+        assert_eq!(mesh.vertices.len(), 6);
+        let handedness: Vec<_> = mesh
+            .indices
+            .iter()
+            .map(|&i| mesh.vertices[i as usize].handedness)
+            .collect();
+        assert_eq!(handedness, [1.0, 1.0, 1.0, -1.0, -1.0, -1.0]);
+        assert_frames_follow_uvs(&mesh.vertices, &mesh.indices);
+    }
+
+    #[test]
+    fn degenerate_uvs_give_finite_tangents() {
+        let path = std::env::temp_dir().join("wgpu-cpp-degenerate-uvs.obj");
+        std::fs::write(
+            &path,
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nvt 0.5 0.5\nvn 0 0 1\n\
+             f 1/1/1 2/1/1 3/1/1\nf 2/1/1 4/1/1 3/1/1\n",
+        )
+        .unwrap();
+        let mesh = load_geometry(&path, LoadOptions::default()).unwrap();
+
+        for v in &mesh.vertices {
+            assert!(v.tangent.is_normalized(), "{v:?}");
+            assert!(v.tangent.dot(v.normal).abs() < 1e-5, "{v:?}");
+        }
+    }
+}
@@ -1,10 +1,20 @@
 struct VertexInput {
     @location(0) position: vec3f,
-    @location(1) tangent: vec3f,
-    @location(2) bitangent: vec3f,
-    @location(3) normal: vec3f,
-    @location(4) color: vec3f,
-    @location(5) uv: vec2f,
+    // w is the handedness of the tangent frame
+    @location(1) tangent: vec4f,
+    @location(2) normal: vec3f,
+    @location(3) color: vec3f,
+    @location(4) uv: vec2f,
+};
+
+// VertexInput as stored by `PackedVertex`
+struct PackedVertexInput {
+    @location(0) position: vec3f,
+    @location(1) tangent: vec4f,
+    // Octahedral encoding of the normal
+    @location(2) normal: vec2f,
+    @location(3) color: vec4f,
+    @location(4) uv: vec2f,
 };
 
 struct VertexOutput {
@@ -15,6 +25,7 @@ struct VertexOutput {
     @location(3) normal: vec3f,
     @location(4) uv: vec2f,
     @location(5) view_direction: vec3f,
+    @location(6) world_position: vec3f,
 };
 
 struct Uniforms {
@@ -29,31 +40,219 @@ struct Uniforms {
 };
 
 struct LightUniforms{
-    directions:array<vec4f,2>,
-    colors:array<vec4f,2>,
+    light_count:u32,
     hardness:f32,
     diffuse:f32,
     specular:f32,
+    shading_model:u32,
+    environment_intensity:f32,
+}
+
+struct Light {
+    position: vec3f,
+    // 0 for no limit
+    range: f32,
+    // Towards the light
+    direction: vec3f,
+    kind: u32,
+    color: vec3f,
+    intensity: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+    // -1 for lights without shadows
+    shadow_layer: i32,
+    shadow_cascades: u32,
+}
+
+struct ShadowUniforms {
+    view_projections: array<mat4x4f, 8>,
+    // View depth where each cascade of the main light ends
+    cascade_splits: vec4f,
+    depth_bias: f32,
+    normal_bias: f32,
+    texel_size: f32,
+    max_distance: f32,
+}
+
+const DIRECTIONAL_LIGHT: u32 = 0u;
+const POINT_LIGHT: u32 = 1u;
+const SPOT_LIGHT: u32 = 2u;
+
+const PHONG: u32 = 0u;
+const PBR: u32 = 1u;
+
+const PI: f32 = 3.14159265;
+// Mip level of the prefiltered environment for a roughness of 1
+const PREFILTERED_MAX_LEVEL: f32 = 4.0;
+
+struct MaterialUniforms{
+    diffuse:vec4f,
+    specular:vec4f,
+    shininess:f32,
+    metallic:f32,
+    roughness:f32,
+    occlusion_strength:f32,
+    emissive:vec4f,
 }
 
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
 @group(0) @binding(1) var<uniform> light_uniforms: LightUniforms;
-@group(0) @binding(2) var texture: texture_2d<f32>;
-@group(0) @binding(3) var texture_sampler: sampler;
-@group(0) @binding(4) var normal_texture: texture_2d<f32>;
-@group(0) @binding(5) var normal_sampler: sampler;
+@group(0) @binding(2) var<storage, read> lights: array<Light>;
+
+@group(1) @binding(0) var<uniform> material: MaterialUniforms;
+@group(1) @binding(1) var texture: texture_2d<f32>;
+@group(1) @binding(2) var texture_sampler: sampler;
+@group(1) @binding(3) var normal_texture: texture_2d<f32>;
+@group(1) @binding(4) var normal_sampler: sampler;
+@group(1) @binding(5) var specular_texture: texture_2d<f32>;
+@group(1) @binding(6) var specular_sampler: sampler;
+// Metalness in blue, roughness in green
+@group(1) @binding(7) var metallic_roughness_texture: texture_2d<f32>;
+@group(1) @binding(8) var metallic_roughness_sampler: sampler;
+@group(1) @binding(9) var occlusion_texture: texture_2d<f32>;
+@group(1) @binding(10) var occlusion_sampler: sampler;
+@group(1) @binding(11) var emissive_texture: texture_2d<f32>;
+@group(1) @binding(12) var emissive_sampler: sampler;
+
+@group(2) @binding(0) var<uniform> shadows: ShadowUniforms;
+@group(2) @binding(1) var shadow_map: texture_depth_2d_array;
+@group(2) @binding(2) var shadow_sampler: sampler_comparison;
+
+// Precomputed by `environment.wgsl`
+@group(3) @binding(0) var irradiance_map: texture_cube<f32>;
+@group(3) @binding(1) var irradiance_sampler: sampler;
+@group(3) @binding(2) var prefiltered_map: texture_cube<f32>;
+@group(3) @binding(3) var prefiltered_sampler: sampler;
+@group(3) @binding(4) var brdf_lut: texture_2d<f32>;
+@group(3) @binding(5) var brdf_lut_sampler: sampler;
+
+// Inverse square falloff, windowed to reach 0 at `range`
+fn distance_attenuation(distance: f32, range: f32) -> f32 {
+    let inverse_square = 1.0 / max(distance * distance, 0.0001);
+    if range <= 0.0 {
+        return inverse_square;
+    }
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window * inverse_square;
+}
+
+// Fraction of the light reaching `world_position`, filtered over 3x3 texels
+fn shadow_visibility(light: Light, world_position: vec3f, normal: vec3f, view_depth: f32) -> f32 {
+    if light.shadow_layer < 0 || view_depth > shadows.max_distance {
+        return 1.0;
+    }
+    var layer = light.shadow_layer;
+    for (var i = 0u; i + 1u < light.shadow_cascades; i++) {
+        if view_depth > shadows.cascade_splits[i] {
+            layer += 1;
+        }
+    }
+    let position = world_position + normal * shadows.normal_bias;
+    let clip = shadows.view_projections[layer] * vec4f(position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2f(0.5, -0.5) + 0.5;
+    if any(uv < vec2f(0.0)) || any(uv > vec2f(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    let depth = ndc.z - shadows.depth_bias;
+    var visibility = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2f(f32(x), f32(y)) * shadows.texel_size;
+            visibility += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, layer, depth);
+        }
+    }
+    return visibility / 9.0;
+}
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(NoH: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = NoH * NoH * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+// Height correlated Smith visibility, includes the 1 / (4 NoL NoV) of the BRDF
+fn visibility_smith_ggx(NoV: f32, NoL: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let ggx_v = NoL * sqrt(NoV * NoV * (1.0 - alpha2) + alpha2);
+    let ggx_l = NoV * sqrt(NoL * NoL * (1.0 - alpha2) + alpha2);
+    return 0.5 / max(ggx_v + ggx_l, 0.0001);
+}
+
+fn fresnel_schlick(VoH: f32, f0: vec3f) -> vec3f {
+    return f0 + (1.0 - f0) * pow(1.0 - VoH, 5.0);
+}
+
+// Cook-Torrance specular plus Lambertian diffuse, times NoL
+fn brdf(N: vec3f, V: vec3f, L: vec3f, base_color: vec3f, metallic: f32, roughness: f32) -> vec3f {
+    let H = normalize(V + L);
+    let NoL = max(dot(N, L), 0.0);
+    let NoV = max(dot(N, V), 0.0001);
+    let NoH = max(dot(N, H), 0.0);
+    let VoH = max(dot(V, H), 0.0);
+    // Perceptual roughness is squared, clamped to keep a visible highlight
+    let alpha = max(roughness * roughness, 0.002);
+    let f0 = mix(vec3f(0.04), base_color, metallic);
+    let F = fresnel_schlick(VoH, f0);
+    let specular = F * distribution_ggx(NoH, alpha) * visibility_smith_ggx(NoV, NoL, alpha);
+    let diffuse = (1.0 - F) * (1.0 - metallic) * base_color / PI;
+    return (diffuse + specular) * NoL;
+}
+
+// Diffuse irradiance plus split sum specular of the environment
+fn environment_lighting(N: vec3f, V: vec3f, base_color: vec3f, metallic: f32, roughness: f32) -> vec3f {
+    let NoV = max(dot(N, V), 0.0001);
+    let f0 = mix(vec3f(0.04), base_color, metallic);
+    // Fresnel over the whole lobe, rough surfaces reflect less at grazing angles
+    let F = f0 + (max(vec3f(1.0 - roughness), f0) - f0) * pow(1.0 - NoV, 5.0);
+    let irradiance = textureSampleLevel(irradiance_map, irradiance_sampler, N, 0.0).rgb;
+    let diffuse = (1.0 - F) * (1.0 - metallic) * base_color * irradiance;
+
+    let R = reflect(-V, N);
+    let prefiltered = textureSampleLevel(prefiltered_map, prefiltered_sampler, R, roughness * PREFILTERED_MAX_LEVEL).rgb;
+    let scale_bias = textureSampleLevel(brdf_lut, brdf_lut_sampler, vec2f(NoV, roughness), 0.0).rg;
+    let specular = prefiltered * (f0 * scale_bias.x + scale_bias.y);
+    return diffuse + specular;
+}
+
+fn octahedral_decode(encoded: vec2f) -> vec3f {
+    var n = vec3f(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    // The lower half is folded over the edges of the square
+    let t = max(-n.z, 0.0);
+    n.x -= select(-t, t, n.x >= 0.0);
+    n.y -= select(-t, t, n.y >= 0.0);
+    return normalize(n);
+}
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
+    return transform_vertex(in);
+}
+
+@vertex
+fn vs_packed(in: PackedVertexInput) -> VertexOutput {
+    var vertex: VertexInput;
+    vertex.position = in.position;
+    vertex.tangent = in.tangent;
+    vertex.normal = octahedral_decode(in.normal);
+    vertex.color = in.color.rgb;
+    vertex.uv = in.uv;
+    return transform_vertex(vertex);
+}
+
+fn transform_vertex(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     let world_position = uniforms.model * vec4f(in.position, 1.0);
     out.position = uniforms.projection * uniforms.view * world_position;
     out.color = in.color; 
-    out.tangent = (uniforms.model * vec4f(in.tangent,0.0)).xyz;
-    out.bitangent = (uniforms.model * vec4f(in.bitangent,0.0)).xyz;
+    out.tangent = (uniforms.model * vec4f(in.tangent.xyz,0.0)).xyz;
     out.normal = (uniforms.model * vec4f(in.normal,0.0)).xyz;
+    out.bitangent = cross(out.normal, out.tangent) * in.tangent.w;
     out.uv = in.uv ;
     out.view_direction = uniforms.camera_world_position - world_position.xyz;
+    out.world_position = world_position.xyz;
     return out;
 }
 
@@ -72,30 +271,57 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4f {
     let V = normalize(in.view_direction);
     var shading = vec3f(0.0);
 
-    let base_color = textureSampleLevel(texture,texture_sampler,in.uv,uniforms.mip_level).rgb;
+    let base_color = textureSampleLevel(texture,texture_sampler,in.uv,uniforms.mip_level).rgb * material.diffuse.rgb;
+    let metallic_roughness = textureSampleLevel(metallic_roughness_texture,metallic_roughness_sampler,in.uv,uniforms.mip_level);
+    let metallic = clamp(metallic_roughness.b * material.metallic, 0.0, 1.0);
+    let roughness = clamp(metallic_roughness.g * material.roughness, 0.0, 1.0);
+    let occlusion_sample = textureSampleLevel(occlusion_texture,occlusion_sampler,in.uv,uniforms.mip_level).r;
+    let occlusion = mix(1.0, occlusion_sample, material.occlusion_strength);
+    let emissive = textureSampleLevel(emissive_texture,emissive_sampler,in.uv,uniforms.mip_level).rgb * material.emissive.rgb;
+    let specular_color = textureSampleLevel(specular_texture,specular_sampler,in.uv,uniforms.mip_level).rgb * material.specular.rgb;
+    // Materials without a shininess use the global hardness
+    let hardness = select(light_uniforms.hardness, material.shininess, material.shininess > 0.0);
+
+
+    let view_depth = (uniforms.view * vec4f(in.world_position, 1.0)).z;
+    for (var i = 0u; i < light_uniforms.light_count; i++) {
+        let light = lights[i];
+        var L = normalize(light.direction);
+        var attenuation = 1.0;
+        if light.kind != DIRECTIONAL_LIGHT {
+            let to_light = light.position - in.world_position;
+            let distance = length(to_light);
+            L = to_light / max(distance, 0.0001);
+            attenuation = distance_attenuation(distance, light.range);
+        }
+        if light.kind == SPOT_LIGHT {
+            let cone_cos = dot(L, normalize(light.direction));
+            attenuation *= smoothstep(light.outer_cone_cos, light.inner_cone_cos, cone_cos);
+        }
+        attenuation *= shadow_visibility(light, in.world_position, normalize(in.normal), view_depth);
+        let radiance = light.intensity * attenuation;
 
+        if light_uniforms.shading_model == PBR {
+            shading += brdf(normalize(N), V, L, base_color, metallic, roughness) * light.color * radiance;
+            continue;
+        }
 
-    for (var i:i32 = 0 ; i<2 ; i++){
-        let L = normalize(light_uniforms.directions[i].xyz);
         let R = reflect(-L, N); // equivalent to 2.0 * dot(N, L) * N - L
 
-        let color = light_uniforms.colors[i].rgb;
-        let diffuse = max(0.0,dot(L,N)) * color;
+        let diffuse = max(0.0,dot(L,N)) * light.color * radiance;
 
         // We clamp the dot product to 0 when it is negative
         let RoV = max(0.0, dot(R, V));
-        let hardness = 32.0;
-        let specular = pow(RoV, light_uniforms.hardness);
+        let specular = pow(RoV, hardness) * specular_color * radiance;
 
         shading += diffuse * light_uniforms.diffuse * base_color + specular * light_uniforms.specular;
     }
+    if light_uniforms.shading_model == PBR {
+        let environment = environment_lighting(normalize(N), V, base_color, metallic, roughness);
+        shading += environment * occlusion * light_uniforms.environment_intensity;
+    }
+    shading += emissive;
 
-    
-    let color = shading;
-    
-
-    let linear_color = pow(color, vec3f(2.2)); // Gamma correction
-
-
-    return vec4f(linear_color, uniforms.color.a);
+    // Linear HDR radiance, tonemapped and encoded by `tonemap.wgsl`
+    return vec4f(shading, uniforms.color.a);
 }
\ No newline at end of file
@@ -0,0 +1,7 @@
+// Light view projection of the shadow map layer being rendered, times the model matrix
+@group(0) @binding(0) var<uniform> light_view_projection: mat4x4f;
+
+@vertex
+fn vs_main(@location(0) position: vec3f) -> @builtin(position) vec4f {
+    return light_view_projection * vec4f(position, 1.0);
+}
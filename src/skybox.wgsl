@@ -0,0 +1,32 @@
+struct SkyboxUniforms {
+    // Of the camera rotation, without its translation
+    inverse_view_projection: mat4x4f,
+    intensity: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) clip_position: vec2f,
+};
+
+@group(0) @binding(0) var<uniform> uniforms: SkyboxUniforms;
+@group(0) @binding(1) var environment: texture_cube<f32>;
+@group(0) @binding(2) var environment_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // A single triangle covering the screen, on the far plane
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec2f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    out.position = vec4f(out.clip_position, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let world = uniforms.inverse_view_projection * vec4f(in.clip_position, 1.0, 1.0);
+    let direction = normalize(world.xyz / world.w);
+    let radiance = textureSampleLevel(environment, environment_sampler, direction, 0.0).rgb;
+    return vec4f(radiance * uniforms.intensity, 1.0);
+}
@@ -0,0 +1,104 @@
+struct TonemapUniforms {
+    exposure: f32,
+    tonemapping: u32,
+    encode_srgb: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) uv: vec2f,
+};
+
+@group(0) @binding(0) var<uniform> uniforms: TonemapUniforms;
+@group(0) @binding(1) var hdr_texture: texture_2d<f32>;
+@group(0) @binding(2) var hdr_sampler: sampler;
+
+const ACES: u32 = 0u;
+const REINHARD: u32 = 1u;
+const AGX: u32 = 2u;
+
+// Stephen Hill's fit of the ACES RRT and ODT
+fn aces(color: vec3f) -> vec3f {
+    let input = mat3x3f(
+        vec3f(0.59719, 0.07600, 0.02840),
+        vec3f(0.35458, 0.90834, 0.13383),
+        vec3f(0.04823, 0.01566, 0.83777),
+    );
+    let output = mat3x3f(
+        vec3f(1.60475, -0.10208, -0.00327),
+        vec3f(-0.53108, 1.10813, -0.07276),
+        vec3f(-0.07367, -0.00605, 1.07602),
+    );
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output * (a / b), vec3f(0.0), vec3f(1.0));
+}
+
+fn reinhard(color: vec3f) -> vec3f {
+    return color / (1.0 + color);
+}
+
+// Polynomial fit of the default AgX contrast curve
+fn agx_contrast(x: vec3f) -> vec3f {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+fn agx(color: vec3f) -> vec3f {
+    let inset = mat3x3f(
+        vec3f(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3f(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3f(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3f(
+        vec3f(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3f(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3f(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var v = inset * color;
+    v = clamp(log2(max(v, vec3f(1e-10))), vec3f(min_ev), vec3f(max_ev));
+    v = agx_contrast((v - min_ev) / (max_ev - min_ev));
+    // The curve outputs display encoded values, go back to linear
+    return pow(max(outset * v, vec3f(0.0)), vec3f(2.2));
+}
+
+fn linear_to_srgb(color: vec3f) -> vec3f {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3f(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3f(0.0031308));
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0, 0), (2, 0) and (0, 2) in uv, covering the whole screen
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let hdr = textureSample(hdr_texture, hdr_sampler, in.uv).rgb * uniforms.exposure;
+    var color: vec3f;
+    switch uniforms.tonemapping {
+        case REINHARD: {
+            color = reinhard(hdr);
+        }
+        case AGX: {
+            color = agx(hdr);
+        }
+        default: {
+            color = aces(hdr);
+        }
+    }
+    color = clamp(color, vec3f(0.0), vec3f(1.0));
+    color = select(color, linear_to_srgb(color), uniforms.encode_srgb != 0u);
+    return vec4f(color, 1.0);
+}